@@ -1,11 +1,11 @@
 
 create table game (
     game_id char(10) not null,
-    admin_id int NOT NULL,
+    admin_id int,
     join_code varchar,
     max_players INT,
-    game_layout JSONB
-    PRIMARY KEY (game_id),
+    game_layout JSONB,
+    PRIMARY KEY (game_id)
 );
 
 create table player (
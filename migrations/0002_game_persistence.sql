@@ -0,0 +1,10 @@
+ALTER TABLE game 
+ADD COLUMN starting_board JSONB;
+
+create table game_move (
+    game char(10) NOT NULL,
+    move_num INT NOT NULL,
+    action JSONB NOT NULL,
+    PRIMARY KEY (game, move_num),
+    FOREIGN KEY (game) REFERENCES game(game_id)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE player 
+ADD COLUMN tank_id SMALLINT;
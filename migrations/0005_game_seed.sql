@@ -0,0 +1,2 @@
+ALTER TABLE game 
+ADD COLUMN game_seed BIGINT;
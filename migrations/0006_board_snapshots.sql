@@ -0,0 +1,7 @@
+create table game_snapshot (
+    game char(10) NOT NULL,
+    move_num INT NOT NULL,
+    board JSONB NOT NULL,
+    PRIMARY KEY (game, move_num),
+    FOREIGN KEY (game) REFERENCES game(game_id)
+);
@@ -0,0 +1,8 @@
+create table game_order (
+    game char(10) NOT NULL,
+    tank_id SMALLINT NOT NULL,
+    order_num INT NOT NULL,
+    action JSONB NOT NULL,
+    PRIMARY KEY (game, tank_id, order_num),
+    FOREIGN KEY (game) REFERENCES game(game_id)
+);
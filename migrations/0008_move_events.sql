@@ -0,0 +1 @@
+ALTER TABLE game_move ADD COLUMN events JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1 @@
+ALTER TABLE game_move ADD COLUMN played_at TIMESTAMPTZ NOT NULL DEFAULT now();
@@ -0,0 +1 @@
+ALTER TABLE game ADD COLUMN started_at TIMESTAMPTZ;
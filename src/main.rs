@@ -1,8 +1,10 @@
 pub mod open_tt;
 pub mod netcode;
 
-use poem::{get, listener::TcpListener, patch, post, EndpointExt, Route, Server};
+use poem::{get, listener::TcpListener, patch, post, put, EndpointExt, Route, Server};
 use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::sync::{broadcast, RwLock};
 
 
 #[tokio::main]
@@ -19,6 +21,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
     let _ =  sqlx::migrate!("./migrations").run(&cpool).await;
 
+    // Restore games that were in progress before the server last stopped
+    let live_games: netcode::LiveGames = match netcode::gamestore::load_live_games(&cpool).await {
+        Ok(games) => {
+            println!("Restored {} live games", games.len());
+            Arc::new(RwLock::new(games))
+        },
+        Err(e) => {
+            println!("Error '{:?}' while restoring live games", e);
+            panic!();
+        }
+    };
+
+    let game_feed: netcode::feeds::GameFeed = broadcast::channel(netcode::feeds::FEED_CAPACITY).0;
+
+    // Hand out action points and hearts and close order phases periodically, each game's rules say how often
+    tokio::spawn(netcode::scheduler::run_ap_ticks(cpool.clone(), live_games.clone(), game_feed.clone()));
+    tokio::spawn(netcode::scheduler::run_heart_spawns(cpool.clone(), live_games.clone(), game_feed.clone()));
+    tokio::spawn(netcode::scheduler::run_phase_deadlines(cpool.clone(), live_games.clone(), game_feed.clone()));
 
     let app = Route::new()
         .at("games", 
@@ -30,7 +50,30 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .delete(netcode::delete_game))
         .at("/games/:game_id/players", 
             post(netcode::post_player))
-        .data(cpool);
+        .at("/games/:game_id/start", 
+            post(netcode::post_game_start))
+        .at("/games/:game_id/board", 
+            get(netcode::get_board))
+        .at("/games/:game_id/results", 
+            get(netcode::get_results))
+        .at("/games/:game_id/history/:turn", 
+            get(netcode::get_history))
+        .at("/games/:game_id/moves", 
+            get(netcode::get_moves))
+        .at("/games/:game_id/replay", 
+            get(netcode::get_replay))
+        .at("/games/:game_id/orders", 
+            put(netcode::put_orders)
+            .get(netcode::get_orders))
+        .at("/games/:game_id/actions", 
+            post(netcode::post_action))
+        .at("/games/:game_id/events", 
+            get(netcode::get_game_events))
+        .at("/games/:game_id/ws", 
+            get(netcode::get_game_socket))
+        .data(cpool)
+        .data(live_games)
+        .data(game_feed);
 
     let _ = Server::new(TcpListener::bind("127.0.0.1:7878"))
         .run(app)
@@ -1,11 +1,22 @@
-use poem::{handler, http::StatusCode, web::{headers::{authorization::{Basic, Credentials}, Authorization, HeaderMap}, Data, Json, Path, TypedHeader}, IntoResponse};
+use poem::{handler, http::StatusCode, web::{headers::{authorization::Basic, Authorization, HeaderMap}, sse::{Event, SSE}, websocket::WebSocket, Data, Json, Path, Query, TypedHeader}, IntoResponse};
 use rand::{distributions::Alphanumeric, random, Rng};
 use serde::{Deserialize, Serialize};
 use sqlx::{query, query_as, PgPool, Postgres};
 use tokio::io::repeat;
+use tokio::sync::RwLock;
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use crate::open_tt::Map;
+use crate::open_tt::{game::{BoardReconstructionError, MoveError}, map_gen::MapGenOptions, Action, Game, GameEvent, GameMode, GameRules, GameState, Map};
+use feeds::{GameFeed, LobbyChange};
+use futures_util::StreamExt;
 mod netutils;
+pub mod gamestore;
+pub mod scheduler;
+pub mod feeds;
+
+
+// Games that are currently being played, keyed by their game ID
+pub type LiveGames = Arc<RwLock<HashMap<String, Game>>>;
 
 
 #[handler]
@@ -37,16 +48,29 @@ pub async fn post_games(
         .map(char::from)
         .collect();
 
+    // Step 1.1: Generate a layout if one was asked for, it has to fit everyone the lobby allows
+    // Big layouts take a while to generate, so this is kept off the async workers
+    let layout = match body.generate_layout.clone() {
+        Some(options) => {
+            let (seed, max_players) = (body.layout_seed.unwrap_or_else(rand::random), body.max_players);
+            match tokio::task::spawn_blocking(move || Map::generate(&options, seed).map(|m| (m.has_room_for(max_players), m))).await {
+                Ok(Ok((true, m))) => Some(m),
+                _ => {return Err(StatusCode::BAD_REQUEST);}
+            }
+        },
+        None => None
+    };
   
     // Step 2: Insert new record for game
     let q_result = sqlx::query!(
         "
-        INSERT INTO game (game_id, join_code, max_players) 
-        VALUES ($1, $2, $3)
+        INSERT INTO game (game_id, join_code, max_players, game_layout) 
+        VALUES ($1, $2, $3, $4)
         ",
         game_id.as_str(),
         body.join_code,
-        i32::from(body.max_players)
+        i32::from(body.max_players),
+        layout.map(sqlx::types::Json) as _
     ).execute(db_conn.0).await.unwrap();
 
     // Step 3: Insert new player for Admin 
@@ -57,7 +81,8 @@ pub async fn post_games(
         Err(e) => match e {
             netutils::PlayerCreationError::DatabaseError(de) => {return Err(StatusCode::BAD_REQUEST)},
             netutils::PlayerCreationError::GameDoesNotExist => {return Err(StatusCode::BAD_REQUEST)},
-            netutils::PlayerCreationError::GameFull => {return Err(StatusCode::BAD_REQUEST)}
+            netutils::PlayerCreationError::GameFull => {return Err(StatusCode::BAD_REQUEST)},
+            netutils::PlayerCreationError::GameStarted => {return Err(StatusCode::BAD_REQUEST)}
         }
     };
 
@@ -81,7 +106,9 @@ pub async fn post_games(
 #[derive(Debug, Serialize, Deserialize)]
 struct GamePostRequest {
     join_code: Option<String>,
-    max_players: u8
+    max_players: u8,
+    generate_layout: Option<MapGenOptions>, // Generate a fresh layout for the game instead of setting one later
+    layout_seed: Option<u64> // Seed for the generated layout, a random one is used if not given
 }
 
 #[derive(Debug, Serialize)]
@@ -98,19 +125,69 @@ impl IntoResponse for GamePostResult {
 
 
 // Handler for patching the data of a game, patch must be done by the admin
+// The layout can be given either as a Map or in the map text format
 #[handler]
 pub async fn patch_game(
     db_conn: Data<&PgPool>,
+    game_feed: Data<&GameFeed>,
     Path(game_id): Path<String>,
     body: Json<GamePatchRequest>,
     TypedHeader(p_auth) : TypedHeader<Authorization<Basic>>
-) -> StatusCode {
+) -> poem::Response {
     // Step 1: Check if sender is authorized as admin of given game
     if !netutils::is_authorized_admin(db_conn.0, &game_id, &p_auth.0).await {
-        return StatusCode::FORBIDDEN;
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    // Step 1.1: Layout and rules are fixed once the game has started
+    let changes_setup = body.0.new_layout.is_some() || body.0.new_layout_text.is_some() || body.0.new_rules.is_some();
+    if changes_setup && netutils::is_game_started(db_conn.0, &game_id).await.unwrap_or(true) {
+        return StatusCode::CONFLICT.into_response();
+    }
+
+    // Step 1.2: Parse a text layout, sending back where it went wrong if it can't be read
+    let new_layout = match (body.0.new_layout, body.0.new_layout_text) {
+        (Some(_), Some(_)) => {return (StatusCode::BAD_REQUEST, "Only one of new_layout and new_layout_text can be given").into_response();},
+        (Some(m), None) => Some(m),
+        (None, Some(text)) => match Map::from_text(&text) {
+            Ok(m) => Some(m),
+            Err(e) => {return (StatusCode::BAD_REQUEST, e.to_string()).into_response();}
+        },
+        (None, None) => None
+    };
+
+    // Step 1.3: Make sure the layout is playable by every player the lobby allows, sending back everything wrong with it if not
+    // Checking a big layout can take a while, so it is kept off the async workers
+    let new_layout = match new_layout {
+        Some(new_map) => {
+            let max_players = match netutils::get_game_capacity(db_conn.0, &game_id).await {
+                Ok((max_players, _)) => max_players,
+                Err(_) => {return StatusCode::INTERNAL_SERVER_ERROR.into_response();}
+            };
+
+            let (new_map, issues) = match tokio::task::spawn_blocking(move || {
+                let issues = new_map.validate(max_players);
+                (new_map, issues)
+            }).await {
+                Ok(r) => r,
+                Err(_) => {return StatusCode::INTERNAL_SERVER_ERROR.into_response();}
+            };
+            if !issues.is_empty() {
+                return (StatusCode::UNPROCESSABLE_ENTITY, serde_json::to_string(&issues).unwrap()).into_response();
+            }
+            Some(new_map)
+        },
+        None => None
+    };
+
+    // Step 1.4: Make sure the rules can be played by, sending back everything wrong with them if not
+    if let Some(new_rules) = &body.0.new_rules {
+        let issues = new_rules.validate();
+        if !issues.is_empty() {
+            return (StatusCode::UNPROCESSABLE_ENTITY, serde_json::to_string(&issues).unwrap()).into_response();
+        }
     }
 
-    
     // Step 2: Create a querry builder with proper head
     let mut q_builder: sqlx::query_builder::QueryBuilder<Postgres> = sqlx::query_builder::QueryBuilder::new(
         "UPDATE game SET "
@@ -119,24 +196,33 @@ pub async fn patch_game(
     // Step 3: Create a seperated builder to push all the comma seperated updates
     let mut seperated = q_builder.separated(",");
 
-    if let Some(new_map) = body.0.new_layout {
+    if let Some(new_map) = new_layout {
         seperated.push("game_layout = ");
-        seperated.push_bind_unseparated(serde_json::to_string(&new_map).unwrap());
+        seperated.push_bind_unseparated(sqlx::types::Json(new_map));
         
     }
 
+    if let Some(new_rules) = body.0.new_rules {
+        seperated.push("game_rules = ");
+        seperated.push_bind_unseparated(sqlx::types::Json(new_rules));
+    }
+
     // Step 4: Finish up query and execute 
     q_builder.push(" WHERE game_id = ");
-    q_builder.push_bind(game_id);
+    q_builder.push_bind(&game_id);
 
     q_builder.build().execute(db_conn.0).await;
 
-    return StatusCode::OK;
+    feeds::publish_lobby_change(game_feed.0, &game_id, LobbyChange::SettingsChanged);
+
+    return StatusCode::OK.into_response();
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct GamePatchRequest {
-    new_layout: Option<Map>
+    new_layout: Option<Map>,
+    new_layout_text: Option<String>,
+    new_rules: Option<GameRules>
 }
 
 
@@ -144,6 +230,7 @@ struct GamePatchRequest {
 #[handler]
 pub async fn post_player(
     db_conn: Data<&PgPool>,
+    game_feed: Data<&GameFeed>,
     Path(game_id): Path<String>,
     r_body: Json<PlayerPostRequest>
 ) -> String {
@@ -170,23 +257,28 @@ pub async fn post_player(
 
 
     // Step 2: Try to register the player
+    let player_name = r_body.0.player_name;
     let p_reg = netutils::register_player_for_game(
         db_conn.0, 
         &game_id, 
-        r_body.0.player_name).await;
+        player_name.clone()).await;
 
     let reg_result = match p_reg {
         Ok(res) => res,
         Err(e) => match e {
             netutils::PlayerCreationError::DatabaseError(de) => {return de.message().to_string();},
             netutils::PlayerCreationError::GameDoesNotExist => {return "Error Crating Player, Game does not exist".to_string();},
-            netutils::PlayerCreationError::GameFull => {return "Error Creating Player, Game Full".to_string();}
+            netutils::PlayerCreationError::GameFull => {return "Error Creating Player, Game Full".to_string();},
+            netutils::PlayerCreationError::GameStarted => {return "Error Creating Player, Game Already Started".to_string();}
         }
     };
     
     let player_passcode = reg_result.p_pass;
     let player_id = reg_result.p_id;
 
+    // Step 3: Let everyone already in the lobby know
+    feeds::publish_lobby_change(game_feed.0, &game_id, LobbyChange::PlayerJoined{player_id, player_name});
+
     return serde_json::to_string(&PlayerPostResponce{player_id, player_passcode}).unwrap();
 }
 
@@ -206,6 +298,7 @@ struct PlayerPostResponce {
 #[handler]
 pub async fn get_game(
     db_conn: Data<&PgPool>, 
+    live_games: Data<&LiveGames>,
     Path(game_id): Path<String>, 
     TypedHeader(auth_header) : TypedHeader<Authorization<Basic>>
 ) -> String {
@@ -213,13 +306,18 @@ pub async fn get_game(
     let is_user = 
         netutils::is_player_in_game(db_conn.0, &game_id, &auth_header.0.username().to_string()).await 
         && netutils::check_user_auth(db_conn.0, auth_header.0).await.is_ok();
-    serde_json::to_string(&netutils::get_game_data(&db_conn.0, &game_id, is_user).await).unwrap()
+    let state = match live_games.0.read().await.get(&game_id) {
+        Some(game) => game.game_state.clone(),
+        None => GameState::Pregame
+    };
+    serde_json::to_string(&netutils::get_game_data(&db_conn.0, &game_id, is_user, state).await).unwrap()
 }
 
 
 #[handler]
 pub async fn delete_game(
     db_conn: Data<&PgPool>, 
+    live_games: Data<&LiveGames>,
     Path(game_id): Path<String>, 
     TypedHeader(p_auth) : TypedHeader<Authorization<Basic>>
 ) -> StatusCode {
@@ -228,39 +326,623 @@ pub async fn delete_game(
         return StatusCode::FORBIDDEN
     }
 
-    // Step 2: Drop all players that are in this game
-    let r1 = sqlx::query!(
-        "
-        DELETE 
-        FROM player 
-        WHERE game = $1
-        ", &game_id
-    ).execute(db_conn.0).await;
+    // Step 2: Hold the live games while deleting so no action or timer writes to the game halfway through
+    let mut games = live_games.0.write().await;
 
-    // Step 2.1: Send back error if there was a problem droping the players
-    match r1 {
-        Err(e) => { 
-            return StatusCode::INTERNAL_SERVER_ERROR;
-        },
-        _=>{}
+    // Step 3: Drop the game along with its players, move log, snapshots and pending orders
+    if gamestore::delete_game(db_conn.0, &game_id).await.is_err() {
+        return StatusCode::INTERNAL_SERVER_ERROR;
     }
 
-    // Step 3: Drop the game it'self
-    let r2 = sqlx::query!(
-        "
-        DELETE
+    // Step 4: Stop running the game
+    games.remove(&game_id);
+
+    return StatusCode::OK;
+}
+
+// Handler for starting a game, placing a tank for every registered player on the stored layout
+// Must be done by the admin, after this no more players can join
+#[handler]
+pub async fn post_game_start(
+    db_conn: Data<&PgPool>,
+    live_games: Data<&LiveGames>,
+    game_feed: Data<&GameFeed>,
+    Path(game_id): Path<String>,
+    TypedHeader(p_auth) : TypedHeader<Authorization<Basic>>
+) -> Result<GameStartResult, StatusCode> {
+    // Step 1: Check if sender is authorized as admin of given game
+    if !netutils::is_authorized_admin(db_conn.0, &game_id, &p_auth.0).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Step 2: Lock the game row so the game can't be started twice and no player can join while the tanks are handed out
+    let mut tx = match db_conn.0.begin().await {
+        Ok(t) => t,
+        Err(_) => {return Err(StatusCode::INTERNAL_SERVER_ERROR);}
+    };
+
+    // Step 3: Get the layout and rules the game will be played with, using the default rules if none were set
+    // The stored starting board is checked rather than the live games, since a started game that failed to restore isn't live
+    let (map, rules) = match sqlx::query!(
+        r#"
+        SELECT game_layout as "game_layout: sqlx::types::Json<Map>", game_rules as "game_rules: sqlx::types::Json<GameRules>",
+            starting_board IS NOT NULL as "started!"
         FROM game
         WHERE game_id = $1
-        ", &game_id
-    );
+        FOR UPDATE
+        "#, &game_id
+    ).fetch_one(&mut *tx).await {
+        Ok(r) if r.started => {return Err(StatusCode::CONFLICT);},
+        Ok(r) => match r.game_layout {
+            Some(m) => (m.0, r.game_rules.map(|g| g.0).unwrap_or_default()),
+            None => {return Err(StatusCode::BAD_REQUEST);}
+        },
+        Err(_) => {return Err(StatusCode::NOT_FOUND);}
+    };
+
+    // Step 4: Hand out the tanks and create the game with a tank for each player
+    let tanks = match netutils::assign_tank_ids(&mut tx, &game_id).await {
+        Ok(t) => t,
+        Err(_) => {return Err(StatusCode::INTERNAL_SERVER_ERROR);}
+    };
+
+    // Picking spawns can take a while on big maps, so it is kept off the async workers
+    let player_count = tanks.len() as u8;
+    let game = match tokio::task::spawn_blocking(move || Game::new(&player_count, &map, &rules, rand::random())).await {
+        Ok(Ok(g)) => g,
+        _ => {return Err(StatusCode::BAD_REQUEST);}
+    };
+
+    // Step 5: Store the game, the tank IDs only stick if the starting board does too
+    if gamestore::save_new_game(&mut tx, &game_id, &game).await.is_err() || tx.commit().await.is_err() {
+        return Err(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    live_games.0.write().await.insert(game_id.clone(), game);
+    feeds::publish_lobby_change(game_feed.0, &game_id, LobbyChange::GameStarted{tanks: tanks.clone()});
+
+    Ok(GameStartResult { tanks: tanks.into_iter().map(|(player_id, tank_id)| PlayerTankAssignment{player_id, tank_id}).collect() })
+}
+
+#[derive(Debug, Serialize)]
+struct GameStartResult {
+    tanks: Vec<PlayerTankAssignment>
+}
+
+#[derive(Debug, Serialize)]
+struct PlayerTankAssignment {
+    player_id: i32,
+    tank_id: u8
+}
+
+impl IntoResponse for GameStartResult {
+    fn into_response(self) -> poem::Response {
+        serde_json::to_string(&self).unwrap().into_response()
+    }
+}
+
+
+// Handler for getting the board of a live game as seen by the sender's tank
+#[handler]
+pub async fn get_board(
+    db_conn: Data<&PgPool>,
+    live_games: Data<&LiveGames>,
+    Path(game_id): Path<String>,
+    TypedHeader(p_auth) : TypedHeader<Authorization<Basic>>
+) -> Result<String, StatusCode> {
+    // Step 1: Check if sender is authorized as a player of the given game
+    if !netutils::is_authorized_player(db_conn.0, &game_id, &p_auth.0).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Step 2: Find the tank belonging to the sender
+    let tank_id = match netutils::get_player_tank_id(db_conn.0, &game_id, p_auth.0.username()).await {
+        Some(t) => t,
+        None => {return Err(StatusCode::FORBIDDEN);}
+    };
+
+    // Step 3: Build the sender's view of the board
+    let games = live_games.0.read().await;
+    let game = match games.get(&game_id) {
+        Some(g) => g,
+        None => {return Err(StatusCode::NOT_FOUND);}
+    };
+
+    let mut view = match game.current_board.view_for(&tank_id) {
+        Some(v) => v,
+        None => {return Err(StatusCode::FORBIDDEN);}
+    };
+
+    let in_phase = game.current_board.rules.game_mode == GameMode::Simultaneous && game.game_state == GameState::InProgress;
+    let phase_seconds = in_phase.then_some(game.current_board.rules.phase_seconds);
+    drop(games);
 
-    // Step 3.1: Send back error if there was a problem droping the players
-    match r1 {
-        Err(e) => { 
-            return StatusCode::INTERNAL_SERVER_ERROR;
+    // Step 4: Let players of simultaneous games know when the current phase ends, the live games are let go of first so the query holds nothing up
+    if let Some(phase_seconds) = phase_seconds {
+        view.phase_deadline = match gamestore::get_timer_anchor(db_conn.0, &game_id, "ResolvePhase").await {
+            Ok(anchor) => Some(scheduler::get_next_due_time(anchor, Duration::from_secs(phase_seconds))),
+            Err(_) => {return Err(StatusCode::INTERNAL_SERVER_ERROR);}
+        };
+    }
+
+    Ok(serde_json::to_string(&view).unwrap())
+}
+
+
+// Handler for getting the final standings of a finished game
+#[handler]
+pub async fn get_results(
+    db_conn: Data<&PgPool>,
+    live_games: Data<&LiveGames>,
+    Path(game_id): Path<String>
+) -> Result<String, StatusCode> {
+    // Step 1: Get the standings from the live game, these only exist once it is over
+    let results = match live_games.0.read().await.get(&game_id) {
+        Some(game) => match game.get_results() {
+            Some(r) => r,
+            None => {return Err(StatusCode::CONFLICT);}
         },
-        _=>{}
+        None => {return Err(StatusCode::NOT_FOUND);}
+    };
+
+    // Step 2: Attach the players controlling each tank
+    let tank_players = match netutils::get_tank_players(db_conn.0, &game_id).await {
+        Ok(t) => t,
+        Err(_) => {return Err(StatusCode::INTERNAL_SERVER_ERROR);}
+    };
+
+    // Tied tanks share a place and the places after them are skipped, so the place is one more than the tanks ahead
+    let mut standings: Vec<Standing> = Vec::new();
+    for tanks in results.places.iter() {
+        let place = standings.len() + 1;
+        for tank_id in tanks {
+            let (player_id, player_name) = match tank_players.get(tank_id) {
+                Some((id, name)) => (Some(*id), Some(name.clone())),
+                None => (None, None)
+            };
+            standings.push(Standing { place, tank_id: *tank_id, player_id, player_name });
+        }
     }
 
-    return StatusCode::OK;
-}
\ No newline at end of file
+    Ok(serde_json::to_string(&standings).unwrap())
+}
+
+#[derive(Debug, Serialize)]
+struct Standing {
+    place: usize,
+    tank_id: u8,
+    player_id: Option<i32>,
+    player_name: Option<String>
+}
+
+
+// Handler for getting the board of a live game as it was after the given number of moves
+// While the game is in progress players only get their own view of the old board, once it is over the whole board is shown
+#[handler]
+pub async fn get_history(
+    db_conn: Data<&PgPool>,
+    live_games: Data<&LiveGames>,
+    Path((game_id, turn)): Path<(String, usize)>,
+    TypedHeader(p_auth) : TypedHeader<Authorization<Basic>>
+) -> Result<String, StatusCode> {
+    // Step 1: Check if sender is authorized as a player of the given game
+    if !netutils::is_authorized_player(db_conn.0, &game_id, &p_auth.0).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Step 2: Find the tank belonging to the sender before taking the live games, it is only needed while the game is in progress
+    let tank_id = netutils::get_player_tank_id(db_conn.0, &game_id, p_auth.0.username()).await;
+
+    // Step 3: Rebuild the board at the requested turn
+    let games = live_games.0.read().await;
+    let game = match games.get(&game_id) {
+        Some(g) => g,
+        None => {return Err(StatusCode::NOT_FOUND);}
+    };
+
+    let board = match game.get_board_at_turn(turn) {
+        Ok(b) => b,
+        Err(BoardReconstructionError::TurnOutOfBounds) => {return Err(StatusCode::NOT_FOUND);},
+        Err(BoardReconstructionError::MoveError(_, _)) => {return Err(StatusCode::INTERNAL_SERVER_ERROR);}
+    };
+
+    let in_progress = game.game_state == GameState::InProgress;
+    drop(games);
+
+    if !in_progress {
+        return Ok(serde_json::to_string(&board).unwrap());
+    }
+
+    // Step 4: Cut the board down to what the sender's tank could see at the time
+    let tank_id = match tank_id {
+        Some(t) => t,
+        None => {return Err(StatusCode::FORBIDDEN);}
+    };
+
+    match board.view_for(&tank_id) {
+        Some(view) => Ok(serde_json::to_string(&view).unwrap()),
+        None => Err(StatusCode::FORBIDDEN)
+    }
+}
+
+
+// Handler for getting a page of the move log, oldest first, starting after the given move number
+// While the game is in progress living players only see the actions of tanks in sight of theirs and what every move did in sight of it
+#[handler]
+pub async fn get_moves(
+    db_conn: Data<&PgPool>,
+    live_games: Data<&LiveGames>,
+    Path(game_id): Path<String>,
+    Query(params): Query<MovesQuery>,
+    TypedHeader(p_auth) : TypedHeader<Authorization<Basic>>
+) -> Result<String, StatusCode> {
+    // Step 1: Check if sender is authorized as a player of the given game
+    if !netutils::is_authorized_player(db_conn.0, &game_id, &p_auth.0).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let tank_id = match netutils::get_player_tank_id(db_conn.0, &game_id, p_auth.0.username()).await {
+        Some(t) => t,
+        None => {return Err(StatusCode::FORBIDDEN);}
+    };
+
+    // Step 2: Get the page from the stored log, one extra move is fetched to tell if there are more after it
+    let after: i32 = match params.after {
+        Some(a) => i32::try_from(a).unwrap_or(i32::MAX),
+        None => -1
+    };
+    let limit = params.limit.unwrap_or(DEFAULT_MOVE_PAGE_SIZE).clamp(1, MAX_MOVE_PAGE_SIZE);
+
+    let mut rows = match sqlx::query!(
+        r#"
+        SELECT move_num, action as "action: sqlx::types::Json<Action>", events as "events: sqlx::types::Json<Vec<GameEvent>>",
+            (EXTRACT(EPOCH FROM played_at) * 1000)::BIGINT as "played_at!"
+        FROM game_move
+        WHERE game = $1 AND move_num > $2
+        ORDER BY move_num
+        LIMIT $3
+        "#, &game_id, after, limit as i64 + 1
+    ).fetch_all(db_conn.0).await {
+        Ok(r) => r,
+        Err(_) => {return Err(StatusCode::INTERNAL_SERVER_ERROR);}
+    };
+
+    let more = rows.len() > limit;
+    rows.truncate(limit);
+
+    // Step 3: Hide what the sender's tank couldn't have seen
+    let games = live_games.0.read().await;
+    let game = match games.get(&game_id) {
+        Some(g) => g,
+        None => {return Err(StatusCode::NOT_FOUND);}
+    };
+
+    let sees_all = game.game_state != GameState::InProgress || game.current_board.graveyard.contains(&tank_id);
+    let section = match (rows.first(), rows.last()) {
+        (Some(first), Some(last)) if !sees_all => match game.get_section(first.move_num as usize..last.move_num as usize + 1) {
+            Ok(s) => Some(s),
+            Err(_) => {return Err(StatusCode::INTERNAL_SERVER_ERROR);}
+        },
+        _ => None
+    };
+    drop(games);
+
+    // Step 3.1: Rebuild what the tank saw of the page without holding up the live games
+    let seen_moves = match section {
+        Some(section) => match tokio::task::spawn_blocking(move || section.get_seen_moves_for(&tank_id)).await {
+            Ok(Ok(e)) => Some(e),
+            _ => {return Err(StatusCode::INTERNAL_SERVER_ERROR);}
+        },
+        None => None
+    };
+
+    // Step 3.2: Who made a hidden move and when is hidden along with it, the row is kept so the page can still be followed on
+    let moves = rows.into_iter()
+        .enumerate()
+        .map(|(ind, row)| {
+            let (shown, outcome) = match &seen_moves {
+                Some(seen) => seen[ind].clone(),
+                None => (true, row.events.0)
+            };
+            MoveLogEntry {
+                move_num: row.move_num as usize,
+                acting_player: if shown {row.action.0.get_acting_player()} else {None},
+                played_at: if shown {Some(row.played_at)} else {None},
+                action: if shown {Some(row.action.0)} else {None},
+                outcome
+            }
+        })
+        .collect();
+
+    Ok(serde_json::to_string(&MovePage{moves, more}).unwrap())
+}
+
+// Number of moves sent in a page when the client doesn't ask for a size, and the most it can ask for
+const DEFAULT_MOVE_PAGE_SIZE: usize = 50;
+const MAX_MOVE_PAGE_SIZE: usize = 500;
+
+#[derive(Debug, Deserialize)]
+struct MovesQuery {
+    after: Option<usize>, // Last move number the client already has, the page starts from the first move if not given
+    limit: Option<usize>
+}
+
+#[derive(Debug, Serialize)]
+struct MovePage {
+    moves: Vec<MoveLogEntry>,
+    more: bool // Whether there are moves after the last one in the page
+}
+
+#[derive(Debug, Serialize)]
+struct MoveLogEntry {
+    move_num: usize,
+    acting_player: Option<u8>, // Tank that made the move, system moves like AP ticks and moves the sender isn't allowed to see have none
+    played_at: Option<i64>, // Unix time in milliseconds the move was stored, left out along with the action
+    action: Option<Action>, // Left out for moves the sender isn't allowed to see yet
+    outcome: Vec<GameEvent> // What the move did, as far as the sender could see
+}
+
+
+// Handler for downloading a finished game as a replay, as JSON unless the binary format is asked for
+#[handler]
+pub async fn get_replay(
+    db_conn: Data<&PgPool>,
+    live_games: Data<&LiveGames>,
+    Path(game_id): Path<String>,
+    Query(params): Query<ReplayQuery>
+) -> Result<poem::Response, StatusCode> {
+    // Step 1: Get the names of the players controlling each tank
+    let tank_players = match netutils::get_tank_players(db_conn.0, &game_id).await {
+        Ok(t) => t,
+        Err(_) => {return Err(StatusCode::INTERNAL_SERVER_ERROR);}
+    };
+
+    // Step 2: Pack up the game, replays show the whole board so only finished games can be downloaded
+    let replay = match live_games.0.read().await.get(&game_id) {
+        Some(game) if game.game_state == GameState::InProgress => {return Err(StatusCode::CONFLICT);},
+        Some(game) => {
+            let player_names = (0..game.starting_board.players.len())
+                .map(|tank_id| match tank_players.get(&(tank_id as u8)) {
+                    Some((_, name)) => name.clone(),
+                    None => String::new()
+                })
+                .collect();
+            game.export_replay(player_names)
+        },
+        None => {return Err(StatusCode::NOT_FOUND);}
+    };
+
+    // Step 3: Encode in the requested format
+    match params.format.as_deref() {
+        None | Some("json") => Ok(poem::Response::builder()
+            .content_type("application/json")
+            .body(replay.to_json())),
+        Some("binary") => Ok(poem::Response::builder()
+            .content_type("application/octet-stream")
+            .body(replay.to_bytes())),
+        Some(_) => Err(StatusCode::BAD_REQUEST)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplayQuery {
+    format: Option<String>
+}
+
+
+// Handler for posting an action to a live game, the action must be made by the sender's own tank
+#[handler]
+pub async fn post_action(
+    db_conn: Data<&PgPool>,
+    live_games: Data<&LiveGames>,
+    game_feed: Data<&GameFeed>,
+    Path(game_id): Path<String>,
+    body: Json<Action>,
+    TypedHeader(p_auth) : TypedHeader<Authorization<Basic>>
+) -> Result<ActionPostResult, StatusCode> {
+    // Step 1: Check if sender is authorized as a player of the given game
+    if !netutils::is_authorized_player(db_conn.0, &game_id, &p_auth.0).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Step 2: Find the tank belonging to the sender and make sure it is the one acting
+    let tank_id = match netutils::get_player_tank_id(db_conn.0, &game_id, p_auth.0.username()).await {
+        Some(t) => t,
+        None => {return Err(StatusCode::FORBIDDEN);}
+    };
+
+    if body.0.get_acting_player() != Some(tank_id) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Step 3: Apply the action to the live game
+    let mut games = live_games.0.write().await;
+    let game = match games.get_mut(&game_id) {
+        Some(g) => g,
+        None => {return Err(StatusCode::NOT_FOUND);}
+    };
+
+    // Step 3.1: Turn down actions the game mode doesn't allow, like out of turn actions, before touching the database
+    if let Err(e) = game.current_board.check_game_mode(&body.0) {
+        return Ok(ActionPostResult::Rejected(MoveError::ActionError(e)));
+    }
+
+    match gamestore::do_and_save_action(db_conn.0, &game_id, game, body.0).await {
+        Ok(events) => {
+            feeds::publish_action(game_feed.0, &game_id, game, &events);
+            Ok(ActionPostResult::Applied)
+        },
+        Err(gamestore::ActionSaveError::MoveError(e)) => Ok(ActionPostResult::Rejected(e)),
+        Err(gamestore::ActionSaveError::DatabaseError(_)) => Err(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+#[derive(Debug, Serialize)]
+enum ActionPostResult {
+    Applied,
+    Rejected(MoveError)
+}
+
+impl IntoResponse for ActionPostResult {
+    fn into_response(self) -> poem::Response {
+        let status = match self {
+            ActionPostResult::Applied => StatusCode::OK,
+            ActionPostResult::Rejected(_) => StatusCode::UNPROCESSABLE_ENTITY
+        };
+        (status, serde_json::to_string(&self).unwrap()).into_response()
+    }
+}
+
+
+// Handler for giving a tank's sealed orders for the current phase of a simultaneous game
+// The orders replace any the tank gave earlier in the phase, sending an empty list withdraws them
+#[handler]
+pub async fn put_orders(
+    db_conn: Data<&PgPool>,
+    live_games: Data<&LiveGames>,
+    Path(game_id): Path<String>,
+    body: Json<Vec<Action>>,
+    TypedHeader(p_auth) : TypedHeader<Authorization<Basic>>
+) -> Result<ActionPostResult, StatusCode> {
+    // Step 1: Check if sender is authorized as a player of the given game
+    if !netutils::is_authorized_player(db_conn.0, &game_id, &p_auth.0).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Step 2: Find the tank belonging to the sender
+    let tank_id = match netutils::get_player_tank_id(db_conn.0, &game_id, p_auth.0.username()).await {
+        Some(t) => t,
+        None => {return Err(StatusCode::FORBIDDEN);}
+    };
+
+    // Step 3: Queue the orders on the live game
+    let mut games = live_games.0.write().await;
+    let game = match games.get_mut(&game_id) {
+        Some(g) => g,
+        None => {return Err(StatusCode::NOT_FOUND);}
+    };
+
+    match gamestore::set_and_save_orders(db_conn.0, &game_id, game, tank_id, body.0).await {
+        Ok(()) => Ok(ActionPostResult::Applied),
+        Err(gamestore::ActionSaveError::MoveError(e)) => Ok(ActionPostResult::Rejected(e)),
+        Err(gamestore::ActionSaveError::DatabaseError(_)) => Err(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+
+// Handler for getting the orders the sender's tank has given so far this phase
+#[handler]
+pub async fn get_orders(
+    db_conn: Data<&PgPool>,
+    live_games: Data<&LiveGames>,
+    Path(game_id): Path<String>,
+    TypedHeader(p_auth) : TypedHeader<Authorization<Basic>>
+) -> Result<String, StatusCode> {
+    // Step 1: Check if sender is authorized as a player of the given game
+    if !netutils::is_authorized_player(db_conn.0, &game_id, &p_auth.0).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Step 2: Find the tank belonging to the sender
+    let tank_id = match netutils::get_player_tank_id(db_conn.0, &game_id, p_auth.0.username()).await {
+        Some(t) => t,
+        None => {return Err(StatusCode::FORBIDDEN);}
+    };
+
+    // Step 3: Send back the tank's pending orders, other tanks' orders stay sealed
+    let games = live_games.0.read().await;
+    match games.get(&game_id) {
+        Some(game) => Ok(serde_json::to_string(&game.pending_orders.get(&tank_id).cloned().unwrap_or_default()).unwrap()),
+        None => Err(StatusCode::NOT_FOUND)
+    }
+}
+
+
+// Handler for opening a WebSocket that pushes lobby changes and game events to the sender as they happen
+// Game events are filtered to what the sender's tank is allowed to see, the same as the board view
+#[handler]
+pub async fn get_game_socket(
+    db_conn: Data<&PgPool>,
+    game_feed: Data<&GameFeed>,
+    Path(game_id): Path<String>,
+    ws: WebSocket,
+    TypedHeader(p_auth) : TypedHeader<Authorization<Basic>>
+) -> Result<impl IntoResponse, StatusCode> {
+    // Step 1: Check if sender is authorized as a player of the given game
+    if !netutils::is_authorized_player(db_conn.0, &game_id, &p_auth.0).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Step 2: Find the tank belonging to the sender, there is none yet if the game hasn't started
+    let player_id = match p_auth.0.username().parse::<i32>() {
+        Ok(p) => p,
+        Err(_) => {return Err(StatusCode::FORBIDDEN);}
+    };
+    let tank_id = netutils::get_player_tank_id(db_conn.0, &game_id, p_auth.0.username()).await;
+
+    // Step 3: Subscribe before upgrading so nothing published in between is missed
+    let feed_rx = game_feed.0.subscribe();
+    Ok(ws.on_upgrade(move |socket| feeds::run_socket(socket, feed_rx, game_id, player_id, tank_id)))
+}
+
+
+// Handler for streaming the game events the sender's tank is allowed to see as Server-Sent Events
+// Each event's ID is its move number, clients that reconnect with a Last-Event-ID get every event after it from the stored log
+#[handler]
+pub async fn get_game_events(
+    db_conn: Data<&PgPool>,
+    live_games: Data<&LiveGames>,
+    game_feed: Data<&GameFeed>,
+    Path(game_id): Path<String>,
+    headers: &HeaderMap,
+    TypedHeader(p_auth) : TypedHeader<Authorization<Basic>>
+) -> Result<SSE, StatusCode> {
+    // Step 1: Check if sender is authorized as a player of the given game
+    if !netutils::is_authorized_player(db_conn.0, &game_id, &p_auth.0).await {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // Step 2: Find the tank belonging to the sender
+    let tank_id = match netutils::get_player_tank_id(db_conn.0, &game_id, p_auth.0.username()).await {
+        Some(t) => t,
+        None => {return Err(StatusCode::FORBIDDEN);}
+    };
+
+    // Step 3: Work out where to pick up from, a fresh client gets the whole game so far
+    let from_move = match headers.get("Last-Event-ID") {
+        Some(last_id) => match last_id.to_str().ok().and_then(|id| id.parse::<usize>().ok()).and_then(|id| id.checked_add(1)) {
+            Some(from_move) => from_move,
+            None => {return Err(StatusCode::BAD_REQUEST);}
+        },
+        None => 0
+    };
+
+    // Step 4: Subscribe before reading the stored events so nothing applied in between is missed
+    let feed_rx = game_feed.0.subscribe();
+
+    let games = live_games.0.read().await;
+    let game = match games.get(&game_id) {
+        Some(g) => g,
+        None => {return Err(StatusCode::NOT_FOUND);}
+    };
+
+    let next_move = game.moves.len();
+    let section = match game.get_section(from_move..next_move) {
+        Ok(s) => s,
+        Err(_) => {return Err(StatusCode::NOT_FOUND);}
+    };
+    drop(games);
+
+    // Step 4.1: Rebuild what the tank missed without holding up the live games, moves the tank saw nothing of are left out
+    let missed: Vec<(usize, Vec<GameEvent>)> = match tokio::task::spawn_blocking(move || section.get_events_for(&tank_id)).await {
+        Ok(Ok(e)) => (from_move..next_move).zip(e).filter(|(_, events)| !events.is_empty()).collect(),
+        _ => {return Err(StatusCode::INTERNAL_SERVER_ERROR);}
+    };
+
+    // Step 5: Send the missed events, then the live ones as they happen
+    let events = futures_util::stream::iter(missed)
+        .chain(feeds::game_event_stream(feed_rx, game_id, tank_id, next_move))
+        .map(|(move_num, events)| Event::message(serde_json::to_string(&events).unwrap()).id(move_num.to_string()));
+
+    Ok(SSE::new(events).keep_alive(Duration::from_secs(15)))
+}
@@ -0,0 +1,125 @@
+// Live feed of lobby changes and game events pushed out to connected players
+use std::collections::HashMap;
+
+use futures_util::{stream, SinkExt, Stream, StreamExt};
+use poem::web::websocket::{Message, WebSocketStream};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::open_tt::{Game, GameEvent};
+
+
+// How many feed items a slow socket can fall behind by before it starts missing them
+pub const FEED_CAPACITY: usize = 1024;
+
+// Every feed item is sent to every socket tagged with its game ID, sockets drop the ones for other games
+pub type GameFeed = broadcast::Sender<(String, FeedItem)>;
+
+
+#[derive(Debug, Clone, Serialize)]
+pub enum FeedItem {
+    Lobby(LobbyChange),
+    Action{move_num : usize, events : HashMap<u8, Vec<GameEvent>>} // Events are already filtered by what each tank is allowed to see
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum LobbyChange {
+    PlayerJoined{player_id : i32, player_name : String},
+    SettingsChanged,
+    GameStarted{tanks : Vec<(i32, u8)>} // Player ID and tank ID of every player in the game
+}
+
+// What a single socket gets sent
+#[derive(Debug, Serialize)]
+enum FeedMessage<'a> {
+    Lobby(&'a LobbyChange),
+    Action{move_num : usize, events : &'a Vec<GameEvent>},
+    Resync // The socket fell behind and missed something, the client should reload the game and reconnect
+}
+
+
+pub fn publish_lobby_change(feed: &GameFeed, game_id: &str, change: LobbyChange) {
+    // Sending fails when nobody is listening, which is fine
+    let _ = feed.send((game_id.to_string(), FeedItem::Lobby(change)));
+}
+
+// Publishes the events of the game's latest move, filtered for every tank against the board the move left behind
+pub fn publish_action(feed: &GameFeed, game_id: &str, game: &Game, events: &[GameEvent]) {
+    let board = &game.current_board;
+    let events_for = board.players.keys()
+        .chain(board.graveyard.iter())
+        .map(|id| (*id, board.filter_events_for(id, events)))
+        .filter(|(_, events)| !events.is_empty())
+        .collect();
+
+    let _ = feed.send((game_id.to_string(), FeedItem::Action{move_num: game.moves.len() - 1, events: events_for}));
+}
+
+
+// Forwards the game's feed items to the socket until either side closes
+// Players that connect before the game starts learn their tank from the GameStarted change
+// A socket that falls behind the feed is sent a Resync and closed, rather than carrying on with a gap the client can't see
+pub async fn run_socket(socket: WebSocketStream, mut feed_rx: broadcast::Receiver<(String, FeedItem)>, game_id: String, player_id: i32, mut tank_id: Option<u8>) {
+    let (mut sink, mut stream) = socket.split();
+
+    loop {
+        tokio::select! {
+            item = feed_rx.recv() => {
+                let item = match item {
+                    Ok((id, item)) if id == game_id => item,
+                    Ok(_) => {continue;},
+                    Err(RecvError::Lagged(_)) => {
+                        let _ = sink.send(Message::Text(serde_json::to_string(&FeedMessage::Resync).unwrap())).await;
+                        let _ = sink.close().await;
+                        break;
+                    },
+                    Err(RecvError::Closed) => {break;}
+                };
+
+                let message = match &item {
+                    FeedItem::Lobby(change) => {
+                        if let LobbyChange::GameStarted { tanks } = change {
+                            tank_id = tanks.iter().find(|(p_id, _)| *p_id == player_id).map(|(_, t_id)| *t_id);
+                        }
+                        FeedMessage::Lobby(change)
+                    },
+                    FeedItem::Action { move_num, events } => match tank_id.and_then(|t_id| events.get(&t_id)) {
+                        Some(events) => FeedMessage::Action { move_num: *move_num, events },
+                        None => {continue;}
+                    }
+                };
+
+                if sink.send(Message::Text(serde_json::to_string(&message).unwrap())).await.is_err() {
+                    break;
+                }
+            },
+            incoming = stream.next() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {break;},
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+}
+
+
+// Turns the feed into a stream of the move number and events of every move the tank is allowed to see, starting at next_move
+// Moves before next_move are skipped since they were already sent from the stored log
+// The stream ends if it falls behind the feed, so the client reconnects and catches up from the stored log instead
+pub fn game_event_stream(feed_rx: broadcast::Receiver<(String, FeedItem)>, game_id: String, tank_id: u8, next_move: usize) -> impl Stream<Item = (usize, Vec<GameEvent>)> {
+    stream::unfold(feed_rx, move |mut feed_rx| {
+        let game_id = game_id.clone();
+        async move {
+            loop {
+                match feed_rx.recv().await {
+                    Ok((id, FeedItem::Action { move_num, mut events })) if id == game_id && move_num >= next_move => {
+                        if let Some(events) = events.remove(&tank_id) {
+                            return Some(((move_num, events), feed_rx));
+                        }
+                    },
+                    Ok(_) => {},
+                    Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => {return None;}
+                }
+            }
+        }
+    })
+}
@@ -0,0 +1,347 @@
+// Repository layer for storing games in the database
+// A game is stored as its starting board plus an ordered log of moves and their events, the current board is rebuilt by replaying them
+// Snapshots of the board are stored every SNAPSHOT_INTERVAL moves so only the end of the log has to be replayed
+use std::collections::HashMap;
+
+use sqlx::{types::Json, PgConnection, PgPool};
+
+use crate::open_tt::{game::{BoardReconstructionError, MoveError}, Action, Board, Game, GameEvent, Map, SNAPSHOT_INTERVAL};
+
+
+#[derive(Debug)]
+pub enum GameLoadError {
+    GameNotStarted,
+    ReplayError(BoardReconstructionError),
+    DatabaseError(sqlx::Error)
+}
+
+#[derive(Debug)]
+pub enum ActionSaveError {
+    MoveError(MoveError),
+    DatabaseError(sqlx::Error)
+}
+
+
+// Stores the starting board of a newly created game, clearing out any old move log
+// Runs on the caller's connection so it can be part of the transaction that starts the game
+pub async fn save_new_game(conn: &mut PgConnection, game_id: &String, game: &Game) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "
+        DELETE
+        FROM game_order
+        WHERE game = $1
+        ", game_id
+    ).execute(&mut *conn).await?;
+
+    sqlx::query!(
+        "
+        DELETE
+        FROM game_snapshot
+        WHERE game = $1
+        ", game_id
+    ).execute(&mut *conn).await?;
+
+    sqlx::query!(
+        "
+        DELETE
+        FROM game_move
+        WHERE game = $1
+        ", game_id
+    ).execute(&mut *conn).await?;
+
+    sqlx::query!(
+        "
+        UPDATE game
+        SET starting_board = $1, game_seed = $2, started_at = now()
+        WHERE game_id = $3
+        ",
+        Json(&game.starting_board) as _,
+        game.seed as i64,
+        game_id
+    ).execute(&mut *conn).await?;
+
+    Ok(())
+}
+
+
+// Deletes a game and everything stored for it, either all of it goes or none of it does
+pub async fn delete_game(db_conn: &PgPool, game_id: &String) -> Result<(), sqlx::Error> {
+    let mut tx = db_conn.begin().await?;
+
+    sqlx::query!(
+        "
+        DELETE
+        FROM game_order
+        WHERE game = $1
+        ", game_id
+    ).execute(&mut *tx).await?;
+
+    sqlx::query!(
+        "
+        DELETE
+        FROM game_snapshot
+        WHERE game = $1
+        ", game_id
+    ).execute(&mut *tx).await?;
+
+    sqlx::query!(
+        "
+        DELETE
+        FROM game_move
+        WHERE game = $1
+        ", game_id
+    ).execute(&mut *tx).await?;
+
+    // The game points at its admin, who is one of the players, so let go of them before the players are dropped
+    sqlx::query!(
+        "
+        UPDATE game
+        SET admin_id = NULL
+        WHERE game_id = $1
+        ", game_id
+    ).execute(&mut *tx).await?;
+
+    sqlx::query!(
+        "
+        DELETE
+        FROM player
+        WHERE game = $1
+        ", game_id
+    ).execute(&mut *tx).await?;
+
+    sqlx::query!(
+        "
+        DELETE
+        FROM game
+        WHERE game_id = $1
+        ", game_id
+    ).execute(&mut *tx).await?;
+
+    tx.commit().await
+}
+
+
+// Loads a game from the database and replays its move log to get the current board
+pub async fn load_game(db_conn: &PgPool, game_id: &String) -> Result<Game, GameLoadError> {
+    // Step 1: Get the layout, starting board and the seed the game draws its randomness from
+    let (map, starting_board, seed) = match sqlx::query!(
+        r#"
+        SELECT game_layout as "game_layout: Json<Map>", starting_board as "starting_board: Json<Board>", game_seed
+        FROM game
+        WHERE game_id = $1
+        "#, game_id
+    ).fetch_one(db_conn).await {
+        Ok(r) => match r.starting_board {
+            Some(b) => (r.game_layout.map(|m| m.0).unwrap_or_default(), b.0, r.game_seed.unwrap_or(0) as u64),
+            None => {return Err(GameLoadError::GameNotStarted);}
+        },
+        Err(e) => {return Err(GameLoadError::DatabaseError(e));}
+    };
+
+    // Step 2: Get the move log in order, along with what happened because of each move
+    let (moves, events): (Vec<Action>, Vec<Vec<GameEvent>>) = match sqlx::query!(
+        r#"
+        SELECT action as "action: Json<Action>", events as "events: Json<Vec<GameEvent>>"
+        FROM game_move
+        WHERE game = $1
+        ORDER BY move_num
+        "#, game_id
+    ).fetch_all(db_conn).await {
+        Ok(list) => list.into_iter().map(|rec| (rec.action.0, rec.events.0)).unzip(),
+        Err(e) => {return Err(GameLoadError::DatabaseError(e));}
+    };
+
+    // Step 3: Get the snapshots in order
+    let snapshots = match sqlx::query!(
+        r#"
+        SELECT board as "board: Json<Board>"
+        FROM game_snapshot
+        WHERE game = $1
+        ORDER BY move_num
+        "#, game_id
+    ).fetch_all(db_conn).await {
+        Ok(list) => list.into_iter().map(|rec| rec.board.0).collect(),
+        Err(e) => {return Err(GameLoadError::DatabaseError(e));}
+    };
+
+    // Step 4: Get the orders given so far this phase, in the order each tank gave them
+    let orders = match sqlx::query!(
+        r#"
+        SELECT tank_id, action as "action: Json<Action>"
+        FROM game_order
+        WHERE game = $1
+        ORDER BY tank_id, order_num
+        "#, game_id
+    ).fetch_all(db_conn).await {
+        Ok(list) => list,
+        Err(e) => {return Err(GameLoadError::DatabaseError(e));}
+    };
+
+    // Step 5: Replay the moves after the latest snapshot, the stored events cover the moves that weren't replayed
+    let mut game = Game::from_snapshots(map, starting_board, snapshots, moves, seed).map_err(GameLoadError::ReplayError)?;
+    game.events = events;
+    for rec in orders {
+        game.pending_orders.entry(rec.tank_id as u8).or_default().push(rec.action.0);
+    }
+    Ok(game)
+}
+
+
+// Loads every started game, used to restore the live games when the server starts
+// A game that can't be loaded is logged and left out so it doesn't stop the rest from running
+pub async fn load_live_games(db_conn: &PgPool) -> Result<HashMap<String, Game>, GameLoadError> {
+    let game_ids = match sqlx::query!(
+        "
+        SELECT game_id
+        FROM game
+        WHERE starting_board IS NOT NULL
+        "
+    ).fetch_all(db_conn).await {
+        Ok(list) => list,
+        Err(e) => {return Err(GameLoadError::DatabaseError(e));}
+    };
+
+    let mut games = HashMap::new();
+    for rec in game_ids {
+        match load_game(db_conn, &rec.game_id).await {
+            Ok(game) => {games.insert(rec.game_id, game);},
+            Err(e) => println!("Error '{:?}' while restoring game {}, skipping it", e, rec.game_id)
+        }
+    }
+
+    Ok(games)
+}
+
+
+// Gets the Unix time in milliseconds a game timer last fired, which is when the last move of the given action kind was stored
+// Timers that haven't fired yet count from when the game started, games started before start times were stored count from now
+pub async fn get_timer_anchor(db_conn: &PgPool, game_id: &String, action_kind: &str) -> Result<i64, sqlx::Error> {
+    sqlx::query!(
+        r#"
+        SELECT (EXTRACT(EPOCH FROM COALESCE(
+            (SELECT max(played_at) FROM game_move WHERE game = $1 AND action ? $2),
+            started_at,
+            now()
+        )) * 1000)::BIGINT as "anchor!"
+        FROM game
+        WHERE game_id = $1
+        "#, game_id, action_kind
+    ).fetch_one(db_conn).await.map(|rec| rec.anchor)
+}
+
+
+// Applies an action to a live game and appends it and its events to the stored move log
+// The move is only kept if both the action is legal and the database write goes through
+pub async fn do_and_save_action(db_conn: &PgPool, game_id: &String, game: &mut Game, action: Action) -> Result<Vec<GameEvent>, ActionSaveError> {
+    // Step 1: Lock the game so moves are numbered one at a time
+    let mut tx = db_conn.begin().await.map_err(ActionSaveError::DatabaseError)?;
+
+    sqlx::query!(
+        "
+        SELECT game_id
+        FROM game
+        WHERE game_id = $1
+        FOR UPDATE
+        ", game_id
+    ).fetch_one(&mut *tx).await.map_err(ActionSaveError::DatabaseError)?;
+
+    // Step 2: Apply the move, nothing has been written yet so an illegal move just drops the transaction
+    let move_num = game.moves.len();
+    let events = game.do_action(action.clone()).map_err(ActionSaveError::MoveError)?;
+
+    // Step 3: Write the move out and commit, if this fails the game in memory is ahead of the database so reload it
+    let stored = match store_move(&mut tx, game_id, game, move_num, &action, &events).await {
+        Ok(()) => tx.commit().await,
+        Err(e) => Err(e)
+    };
+
+    if let Err(e) = stored {
+        if let Ok(stored) = load_game(db_conn, game_id).await {
+            *game = stored;
+        }
+        return Err(ActionSaveError::DatabaseError(e));
+    }
+
+    Ok(events)
+}
+
+// Writes a move that has just been applied to the game, along with anything else the move changes in storage
+async fn store_move(conn: &mut PgConnection, game_id: &String, game: &Game, move_num: usize, action: &Action, events: &Vec<GameEvent>) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "
+        INSERT INTO game_move (game, move_num, action, events)
+        VALUES ($1, $2, $3, $4)
+        ",
+        game_id,
+        move_num as i32,
+        Json(action) as _,
+        Json(events) as _
+    ).execute(&mut *conn).await?;
+
+    // Resolving a phase uses up the orders given in it
+    if let Action::ResolvePhase(_) = action {
+        sqlx::query!(
+            "
+            DELETE
+            FROM game_order
+            WHERE game = $1
+            ", game_id
+        ).execute(&mut *conn).await?;
+    }
+
+    // Store the snapshot if the move was due one
+    if game.moves.len().is_multiple_of(SNAPSHOT_INTERVAL) {
+        sqlx::query!(
+            "
+            INSERT INTO game_snapshot (game, move_num, board)
+            VALUES ($1, $2, $3)
+            ",
+            game_id,
+            game.moves.len() as i32,
+            Json(&game.current_board) as _
+        ).execute(&mut *conn).await?;
+    }
+
+    Ok(())
+}
+
+
+// Replaces a tank's orders for the current phase of a simultaneous game, both in the live game and the database
+pub async fn set_and_save_orders(db_conn: &PgPool, game_id: &String, game: &mut Game, tank_id: u8, orders: Vec<Action>) -> Result<(), ActionSaveError> {
+    // Step 1: Swap out the tank's stored orders
+    let mut tx = db_conn.begin().await.map_err(ActionSaveError::DatabaseError)?;
+
+    sqlx::query!(
+        "
+        DELETE
+        FROM game_order
+        WHERE game = $1 AND tank_id = $2
+        ", game_id, i16::from(tank_id)
+    ).execute(&mut *tx).await.map_err(ActionSaveError::DatabaseError)?;
+
+    for (order_num, order) in orders.iter().enumerate() {
+        sqlx::query!(
+            "
+            INSERT INTO game_order (game, tank_id, order_num, action)
+            VALUES ($1, $2, $3, $4)
+            ",
+            game_id,
+            i16::from(tank_id),
+            order_num as i32,
+            Json(order) as _
+        ).execute(&mut *tx).await.map_err(ActionSaveError::DatabaseError)?;
+    }
+
+    // Step 2: Queue the orders, dropping the transaction rolls the database back if they aren't allowed
+    game.set_orders(tank_id, orders).map_err(ActionSaveError::MoveError)?;
+
+    // Step 3: Commit, if this fails the game in memory is ahead of the database so reload it
+    if let Err(e) = tx.commit().await {
+        if let Ok(stored) = load_game(db_conn, game_id).await {
+            *game = stored;
+        }
+        return Err(ActionSaveError::DatabaseError(e));
+    }
+
+    Ok(())
+}
@@ -1,8 +1,12 @@
+use std::collections::HashMap;
+
 use rand::{distributions::Alphanumeric, Rng};
 use serde::Serialize;
-use sqlx::{query, query_as, Error, PgPool};
+use sqlx::{query, query_as, Error, PgConnection, PgPool};
 use poem::web::headers::authorization;
 
+use crate::open_tt::GameState;
+
 pub async fn check_user_auth(db_conn: &PgPool, auth_data: authorization::Basic) -> Result<(), UserAuthError> {
     let player_id = match auth_data.username().parse::<i32>() {
         Ok(p) => p,
@@ -34,7 +38,7 @@ pub enum UserAuthError {
 
 
 // Returns metadata about a game lobby
-pub async fn get_game_data(db_conn: &PgPool, game_id: &String, as_user: bool) -> GameData {
+pub async fn get_game_data(db_conn: &PgPool, game_id: &String, as_user: bool, state: GameState) -> GameData {
     let players = match as_user {
         true => Some(get_players_in_game(db_conn, game_id).await.unwrap_or(Vec::new())),
         false => None
@@ -42,14 +46,15 @@ pub async fn get_game_data(db_conn: &PgPool, game_id: &String, as_user: bool) ->
 
     let space = get_game_capacity(db_conn, game_id).await.unwrap_or((0, 0));
 
-    GameData{game_id: game_id.to_string(), space, players}
+    GameData{game_id: game_id.to_string(), space, players, state}
 }
 
 #[derive(Debug, Serialize)]
 pub struct GameData {
     game_id: String,
     space: (u8, u8),
-    players: Option<Vec<String>>
+    players: Option<Vec<String>>,
+    state: GameState
 }
 
 // Gets the capacity of a game and the number of active players
@@ -96,13 +101,42 @@ pub async fn get_players_in_game(db_conn: &PgPool, game_id: &String) -> Result<V
 
 // Adds a new player to the database and points it at the given game
 pub async fn register_player_for_game(db_conn: &PgPool, game_id: &String, name: String) -> Result<NewPlayer, PlayerCreationError> {
-    // Step 1: Check if game is full
-    let capacity :u8= match get_game_capacity(db_conn, &game_id).await {
-        Ok((m, c)) => m - c,
-        Err(e) => {return Err(PlayerCreationError::DatabaseError(e));}
+    // Step 1: Lock the game row so the game can't be started while the player is being added
+    let mut tx = match db_conn.begin().await {
+        Ok(t) => t,
+        Err(e) => {return Err(to_creation_error(e));}
+    };
+
+    let game = match sqlx::query!(
+        r#"
+        SELECT max_players, starting_board IS NOT NULL as "started!"
+        FROM game
+        WHERE game_id = $1
+        FOR UPDATE
+        "#, game_id
+    ).fetch_one(&mut *tx).await {
+        Ok(r) => r,
+        Err(e) => {return Err(to_creation_error(e));}
     };
 
-    if capacity == 0 {
+    // Step 1.1: Players can't join once the tanks have been placed
+    if game.started {
+        return Err(PlayerCreationError::GameStarted);
+    }
+
+    // Step 1.2: Check if game is full
+    let player_count = match sqlx::query!(
+        "
+        SELECT count(*)
+        FROM player
+        WHERE game = $1
+        ", game_id
+    ).fetch_one(&mut *tx).await {
+        Ok(r) => r.count.unwrap_or(0),
+        Err(e) => {return Err(to_creation_error(e));}
+    };
+
+    if player_count >= i64::from(game.max_players.unwrap_or(0)) {
         return Err(PlayerCreationError::GameFull);
     }
 
@@ -123,13 +157,17 @@ pub async fn register_player_for_game(db_conn: &PgPool, game_id: &String, name:
         &name,
         &p_pass,
         &game_id
-    ).fetch_one(db_conn).await;
+    ).fetch_one(&mut *tx).await;
 
     let p_id: i32 = match create_player_result {
         Ok(r) => r.player_id,
-        Err(e) => {return Err(PlayerCreationError::DatabaseError(e.into_database_error().expect("query!() managed to return a non-database error, which is a big problem.")));}
+        Err(e) => {return Err(to_creation_error(e));}
     };
 
+    if let Err(e) = tx.commit().await {
+        return Err(to_creation_error(e));
+    }
+
 
     // Setp 3: Compile data for output
     return Ok(NewPlayer { p_id, p_pass})
@@ -140,9 +178,18 @@ pub struct NewPlayer {pub p_id : i32, pub p_pass : String}
 pub enum PlayerCreationError {
     GameDoesNotExist,
     GameFull,
+    GameStarted,
     DatabaseError(Box<dyn sqlx::error::DatabaseError>)
 }
 
+// Errors that don't come from the database itself mean the game row wasn't there
+fn to_creation_error(e: Error) -> PlayerCreationError {
+    match e.into_database_error() {
+        Some(de) => PlayerCreationError::DatabaseError(de),
+        None => PlayerCreationError::GameDoesNotExist
+    }
+}
+
 
 pub async fn is_player_in_game(db_conn: &PgPool, game_id: &String, player_id: &String) -> bool {
     (match sqlx::query!(
@@ -229,4 +276,84 @@ pub async fn is_authorized_admin(db_conn: &PgPool, game_id: &String, p_auth : &a
     }
 
     return true;
-}
\ No newline at end of file
+}
+
+// Gets the id of the tank a player controls on the game board
+// Tanks are only assigned once the game has started
+pub async fn get_player_tank_id(db_conn: &PgPool, game_id: &str, player_id: &str) -> Option<u8> {
+    let player_id = match player_id.parse::<i32>() {
+        Ok(p) => p,
+        Err(_) => {return None}
+    };
+
+    match sqlx::query!(
+        "
+        SELECT tank_id
+        FROM player
+        WHERE player_id = $1 AND game = $2
+        ", player_id, game_id
+    ).fetch_one(db_conn).await {
+        Ok(rec) => rec.tank_id.and_then(|t| u8::try_from(t).ok()),
+        Err(_) => None
+    }
+}
+
+
+// Assigns each player in a game the id of their tank on the game board
+// Tanks are handed out in the order players registered, matching the ids given out by Game::new
+// Returns the list of (player_id, tank_id) pairs
+pub async fn assign_tank_ids(conn: &mut PgConnection, game_id: &String) -> Result<Vec<(i32, u8)>, Error> {
+    let players = sqlx::query!(
+        "
+        SELECT player_id
+        FROM player
+        WHERE game = $1
+        ORDER BY player_id
+        ", game_id
+    ).fetch_all(&mut *conn).await?;
+
+    let mut assigned = Vec::new();
+    for (tank_id, rec) in players.iter().enumerate() {
+        sqlx::query!(
+            "
+            UPDATE player
+            SET tank_id = $1
+            WHERE player_id = $2
+            ", tank_id as i16, rec.player_id
+        ).execute(&mut *conn).await?;
+        assigned.push((rec.player_id, tank_id as u8));
+    }
+
+    Ok(assigned)
+}
+
+
+// Gets the player controlling each tank in a started game, as a map from tank ID to (player_id, player_name)
+pub async fn get_tank_players(db_conn: &PgPool, game_id: &String) -> Result<HashMap<u8, (i32, String)>, Error> {
+    let players = sqlx::query!(
+        "
+        SELECT player_id, player_name, tank_id
+        FROM player
+        WHERE game = $1 AND tank_id IS NOT NULL
+        ", game_id
+    ).fetch_all(db_conn).await?;
+
+    Ok(players.into_iter()
+        .filter_map(|rec| {
+            let tank_id = u8::try_from(rec.tank_id?).ok()?;
+            Some((tank_id, (rec.player_id, rec.player_name)))
+        })
+        .collect())
+}
+
+
+// Checks if a game has been started, games are started once their starting board has been stored
+pub async fn is_game_started(db_conn: &PgPool, game_id: &String) -> Result<bool, Error> {
+    sqlx::query!(
+        r#"
+        SELECT starting_board IS NOT NULL as "started!"
+        FROM game
+        WHERE game_id = $1
+        "#, game_id
+    ).fetch_one(db_conn).await.map(|rec| rec.started)
+}
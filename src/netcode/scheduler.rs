@@ -0,0 +1,139 @@
+// Background tasks that advance live games over time
+use std::{collections::HashMap, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use sqlx::PgPool;
+use tokio::time::{interval, Instant};
+
+use crate::open_tt::{Action, Game, GameMode, GameRules, GameState};
+use super::{feeds::{self, GameFeed}, gamestore, LiveGames};
+
+
+// How often the timers check whether any game is due for its next event
+const TIMER_POLL_PERIOD: Duration = Duration::from_secs(10);
+
+
+// Gives every living tank in every in-progress game an action point, once per AP period of that game's rules
+// Turn based games hand out AP at the start of each turn instead, so they are skipped
+// Runs forever, should be spawned as its own task
+pub async fn run_ap_ticks(db_conn: PgPool, live_games: LiveGames, game_feed: GameFeed) {
+    run_game_timer(
+        db_conn, 
+        live_games, 
+        game_feed, 
+        "APTick", 
+        |rules| Duration::from_secs(rules.ap_tick_seconds), 
+        |game| match game.current_board.rules.game_mode {
+            GameMode::RealTime | GameMode::Simultaneous => Some(Action::APTick),
+            GameMode::TurnBased => None
+        }
+    ).await
+}
+
+
+// Spawns a heart pickup at a random empty position in every in-progress game, once per spawn period of that game's rules
+// Runs forever, should be spawned as its own task
+pub async fn run_heart_spawns(db_conn: PgPool, live_games: LiveGames, game_feed: GameFeed) {
+    // The chosen position goes in the move log so replays put the heart in the same place
+    run_game_timer(
+        db_conn, 
+        live_games, 
+        game_feed, 
+        "SpawnHeart", 
+        |rules| Duration::from_secs(rules.heart_spawn_seconds), 
+        |game| game.pick_heart_spawn().map(Action::SpawnHeart)
+    ).await
+}
+
+
+// Resolves the orders given in every simultaneous game once its phase deadline has passed
+// Runs forever, should be spawned as its own task
+pub async fn run_phase_deadlines(db_conn: PgPool, live_games: LiveGames, game_feed: GameFeed) {
+    run_game_timer(
+        db_conn, 
+        live_games, 
+        game_feed, 
+        "ResolvePhase", 
+        |rules| Duration::from_secs(rules.phase_seconds), 
+        |game| game.get_phase_resolution()
+    ).await
+}
+
+
+// Applies a system action to each in-progress game whenever its period has passed
+// When a game is first seen the timer picks up from the last time its action was stored, so restarting the server doesn't reset it
+// If there is nothing to do when the timer is due it waits another period, keeping to the same schedule
+async fn run_game_timer(
+    db_conn: PgPool, 
+    live_games: LiveGames, 
+    game_feed: GameFeed, 
+    action_kind: &'static str, // Name of the action the timer stores, used to find when it last fired
+    period_of: impl Fn(&GameRules) -> Duration, 
+    next_action: impl Fn(&Game) -> Option<Action>
+) {
+    let mut next_due: HashMap<String, Instant> = HashMap::new();
+    let mut ticker = interval(TIMER_POLL_PERIOD);
+
+    loop {
+        ticker.tick().await;
+        let now = Instant::now();
+
+        let mut games = live_games.write().await;
+        for (game_id, game) in games.iter_mut() {
+            if game.game_state != GameState::InProgress {
+                continue;
+            }
+
+            // Timers can't fire more often than they are checked
+            let period = period_of(&game.current_board.rules).max(TIMER_POLL_PERIOD);
+            let due = match next_due.get(game_id) {
+                Some(d) => *d,
+                None => {
+                    let since_fired = match gamestore::get_timer_anchor(&db_conn, game_id, action_kind).await {
+                        Ok(anchor) => Duration::from_millis(get_unix_millis().saturating_sub(anchor).max(0) as u64),
+                        Err(e) => {
+                            println!("Error '{:?}' while finding the last {} of game {}", e, action_kind, game_id);
+                            Duration::ZERO
+                        }
+                    };
+                    now + period.saturating_sub(since_fired)
+                }
+            };
+
+            if now < due {
+                next_due.insert(game_id.clone(), due);
+                continue;
+            }
+
+            let action = match next_action(game) {
+                Some(a) => a,
+                None => {
+                    let mut next = due + period;
+                    while next <= now {
+                        next += period;
+                    }
+                    next_due.insert(game_id.clone(), next);
+                    continue;
+                }
+            };
+
+            // The stored move is where the timer counts from now
+            next_due.insert(game_id.clone(), now + period);
+            match gamestore::do_and_save_action(&db_conn, game_id, game, action).await {
+                Ok(events) => feeds::publish_action(&game_feed, game_id, game, &events),
+                Err(e) => println!("Error '{:?}' while running timer for game {}", e, game_id)
+            }
+        }
+    }
+}
+
+
+// Gets the Unix time in milliseconds the timer that last fired at the anchor is next due, on the same schedule the timers keep
+pub fn get_next_due_time(anchor: i64, period: Duration) -> i64 {
+    let period_ms = period.max(TIMER_POLL_PERIOD).as_millis() as i64;
+    let periods_passed = get_unix_millis().saturating_sub(anchor).max(0) / period_ms;
+    anchor + period_ms * (periods_passed + 1)
+}
+
+fn get_unix_millis() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as i64).unwrap_or(0)
+}
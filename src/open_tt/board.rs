@@ -1,5 +1,5 @@
 // Defines a game board
-use std::{cmp::{max, min}, collections::HashMap};
+use std::{cmp::{max, min}, collections::{HashMap, HashSet}};
 use super::*;
 
 
@@ -17,17 +17,13 @@ impl Board {
 
     // Get everything at the board position to check if it is something that would prevent traverse
     // If nothing in it prevents traverse, or the position is empty, return true
-    fn is_pos_traversable(&self, pos : &BoardPos) -> bool {
-        match self.get_things_at_pos(pos)
+    pub fn is_pos_traversable(&self, pos : &BoardPos) -> bool {
+        self.get_things_at_pos(pos)
             .iter()
-            .map(|thing| match *thing { // Convert things at pos to determine wether they would block traverse
-                BoardThing::ObjectThing => self.objects.get(pos).unwrap().type_flags & INPASSABLE != 0,
+            .all(|thing| match *thing { // Tanks block traverse, as do objects flagged as inpassable
+                BoardThing::ObjectThing => self.objects.get(pos).unwrap().type_flags & INPASSABLE == 0,
                 BoardThing::PlayerThing(_) => false
             })
-            .reduce(|acc, e| acc && e) {
-                Some(t) => t,
-                None => true
-            }
     }
 
     fn is_pos_in_bounds(&self, pos : &BoardPos) -> bool {
@@ -41,6 +37,57 @@ impl Board {
         }
     }
 
+    // Checks if anything that blocks sight lies on the grid line between two positions
+    // The line is always traced from the lower of the two positions, so sight works the same both ways
+    pub fn has_line_of_sight(&self, from : &BoardPos, to : &BoardPos) -> bool {
+        if (from.1, from.0) <= (to.1, to.0) {
+            self.is_line_clear(from, to)
+        } else {
+            self.is_line_clear(to, from)
+        }
+    }
+
+    // Traces the line with Bresenham's algorithm, things at either end of the line don't block it
+    // Bresenham picks different cells depending on which end it starts from, so this should only be used through has_line_of_sight
+    fn is_line_clear(&self, from : &BoardPos, to : &BoardPos) -> bool {
+        let (mut x, mut y) = (i32::from(from.0), i32::from(from.1));
+        let (t_x, t_y) = (i32::from(to.0), i32::from(to.1));
+
+        let d_x = (t_x - x).abs();
+        let d_y = -(t_y - y).abs();
+        let s_x = if x < t_x { 1 } else { -1 };
+        let s_y = if y < t_y { 1 } else { -1 };
+        let mut err = d_x + d_y;
+
+        loop {
+            if x == t_x && y == t_y {
+                return true;
+            }
+
+            let e2 = 2 * err;
+            if e2 >= d_y {
+                err += d_y;
+                x += s_x;
+            }
+            if e2 <= d_x {
+                err += d_x;
+                y += s_y;
+            }
+
+            if x == t_x && y == t_y {
+                return true;
+            }
+
+            // Points on the line are between the two ends so are always on the board
+            let pos = BoardPos(x as u16, y as u16);
+            if let Some(object) = self.objects.get(&pos) {
+                if object.type_flags & BLOCK_SIGHT != 0 {
+                    return false;
+                }
+            }
+        }
+    }
+
     // Internal function, get a list of all the board things at the given position 
     fn get_things_at_pos(&self, pos: &BoardPos) -> Vec<BoardThing> {
         let mut out: Vec<BoardThing> = Vec::new();
@@ -74,38 +121,35 @@ impl Board {
         return None;
     }
 
-    // Take one action point from the player at the target position
-    fn take_ap_from_player(&mut self, p_id: &u8) -> Result<(), AccessError> {
+    // Take the given number of action points from the player with the given id
+    fn take_ap_from_player(&mut self, p_id: &u8, amount: u8) -> Result<(), AccessError> {
         let mut player = match self.players.get_mut(p_id) {
             Some(p) => p,
             None => {return Err(AccessError::CouldNotFindPlayer);}
         };
 
-        if player.action_points == 0 {
+        if player.action_points < amount {
             return Err(AccessError::PlayerAPInsufficient);
         }
 
-        player.action_points -= 1;
+        player.action_points -= amount;
 
         Ok(())
     }
 
-    // Damage all things at the given position, returns a list of board things that have been destroyed
-    fn damage_things_at_board_pos(&mut self, pos: &BoardPos) -> Vec<BoardThing> {
+    // Damage all things at the given position, returns the hits, kills and destroyed objects
+    fn damage_things_at_board_pos(&mut self, pos: &BoardPos) -> Vec<GameEvent> {
         let things = self.get_things_at_pos(pos);
-        let mut out: Vec<BoardThing> = Vec::new();
+        let mut out: Vec<GameEvent> = Vec::new();
         for board_thing in things {
             match board_thing {
                 BoardThing::PlayerThing(p_id) => {
-                    let kill = self.damage_and_kill_player(&p_id);
-                    if kill == PlayerHitResult::PlayerKilled {
-                        out.push(BoardThing::PlayerThing(p_id));
-                    }
+                    out.extend(self.damage_player(&p_id));
                 }
                 BoardThing::ObjectThing => {
                     let kill = self.damage_and_destroy_board_pos(pos);
                     if kill == BoardObjectHitResult::Destroyed {
-                        out.push(BoardThing::ObjectThing);
+                        out.push(GameEvent::ObjectDestroyed { pos: pos.clone() });
                     }
                 }
             }
@@ -113,13 +157,22 @@ impl Board {
         return out;
     }
 
+    // Takes a hitpoint from the player, returns the hit and the kill if it was their last one
+    fn damage_player(&mut self, p_id: &u8) -> Vec<GameEvent> {
+        match self.damage_and_kill_player(p_id) {
+            PlayerHitResult::PlayerKilled => vec![GameEvent::Hit { tank: *p_id, hitpoints: 0 }, GameEvent::Killed { tank: *p_id }],
+            PlayerHitResult::PlayerAlive => vec![GameEvent::Hit { tank: *p_id, hitpoints: self.players[p_id].hitpoints }]
+        }
+    }
+
     // Damages the player and removes it from living player map if killed
     // Should only be called internally, assumes that the given player ID is valid
     fn damage_and_kill_player(&mut self, p_id: &u8) -> PlayerHitResult {
         let mut player = self.players.remove(p_id).unwrap();
-        player.hitpoints -= 1;
+        player.hitpoints = player.hitpoints.saturating_sub(1);
         if player.hitpoints == 0 {
             drop(player);
+            self.graveyard.push(*p_id);
             return PlayerHitResult::PlayerKilled;
         }
         self.players.insert(*p_id, player);
@@ -146,7 +199,7 @@ impl Board {
     }
 
     // Tries to move a player to the target position
-    fn apply_move_action(&mut self, p_id : &u8, t_pos : &BoardPos) -> Result<(), ActionError> {
+    fn apply_move_action(&mut self, p_id : &u8, t_pos : &BoardPos) -> Result<Vec<GameEvent>, ActionError> {
         if !self.is_pos_traversable(t_pos) {
             return Err(ActionError::SpaceOccupied);
         }
@@ -160,11 +213,11 @@ impl Board {
             Some(p) => &p.position
         };
 
-        if p_pos.get_grid_dist(t_pos) > PLAYER_MOVE_DIST {
+        if p_pos.get_grid_dist(t_pos) > self.rules.move_distance {
             return Err(ActionError::TargetTooFar);
         }
 
-        let take_result = self.take_ap_from_player(p_id);
+        let take_result = self.take_ap_from_player(p_id, 1);
         match take_result {
             Ok(_) => {}
             Err(e) => match e {
@@ -173,26 +226,54 @@ impl Board {
             }
         }
 
-        self.players.get_mut(p_id).unwrap().position = t_pos.clone();
+        let from = std::mem::replace(&mut self.players.get_mut(p_id).unwrap().position, t_pos.clone());
+        let mut events = vec![GameEvent::Moved { tank: *p_id, from: Some(from), to: Some(t_pos.clone()) }];
+        events.extend(self.collect_pickup(p_id, t_pos));
 
-        return Ok(());
+        return Ok(events);
     }
 
-    fn apply_shoot_action(&mut self, p_id : &u8, t_pos : &BoardPos) -> Result<(), ActionError> {
-        if !self.is_pos_in_bounds(t_pos) {
-            return Err(ActionError::OutOfBounds);
+    // Lets the given player collect any pickup at the given position
+    fn collect_pickup(&mut self, p_id : &u8, pos : &BoardPos) -> Option<GameEvent> {
+        let pos_flags = match self.objects.get(pos) {
+            Some(object) => object.type_flags,
+            None => {return None;}
+        };
+
+        if pos_flags & board_object::PICKUP == 0 {
+            return None;
         }
 
-        let p_pos = match self.players.get(p_id) {
-            None => {return Err(ActionError::InvalidPlayerID);},
-            Some(p) => &p.position
-        };
+        let _ = self.objects.remove(pos);
 
-        if p_pos.get_grid_dist(t_pos) > PLAYER_SHOOT_DIST {
-            return Err(ActionError::TargetTooFar);
+        if pos_flags & board_object::RESTORES_HP != 0 {
+            let player = self.players.get_mut(p_id).unwrap();
+            if player.hitpoints < self.rules.heart_hp_cap {
+                player.hitpoints += 1;
+            }
         }
 
-        let take_result = self.take_ap_from_player(p_id);
+        Some(GameEvent::PickupCollected { tank: *p_id, pos: pos.clone() })
+    }
+
+    // Gets every in bounds position with nothing in it
+    pub fn get_empty_positions(&self) -> Vec<BoardPos> {
+        let mut out: Vec<BoardPos> = Vec::new();
+        for x in 0..self.size_x {
+            for y in 0..self.size_y {
+                let pos = BoardPos(x, y);
+                if self.get_things_at_pos(&pos).is_empty() {
+                    out.push(pos);
+                }
+            }
+        }
+        out
+    }
+
+    fn apply_shoot_action(&mut self, p_id : &u8, t_pos : &BoardPos) -> Result<Vec<GameEvent>, ActionError> {
+        self.check_shot(p_id, t_pos)?;
+
+        let take_result = self.take_ap_from_player(p_id, 1);
         match take_result {
             Ok(_) => {}
             Err(e) => match e {
@@ -201,31 +282,68 @@ impl Board {
             }
         }
 
-        let _ = self.damage_things_at_board_pos(t_pos);
+        let mut events = vec![GameEvent::Shot { tank: Some(*p_id), target: t_pos.clone() }];
+        events.extend(self.damage_things_at_board_pos(t_pos));
 
-        return Ok(());
+        return Ok(events);
     }
 
-    fn apply_give_ap_action(&mut self, p_id : &u8, t_pos : &BoardPos) -> Result<(), ActionError> {
+    // Checks the player could shoot at the target position, without spending any AP
+    fn check_shot(&self, p_id : &u8, t_pos : &BoardPos) -> Result<(), ActionError> {
         if !self.is_pos_in_bounds(t_pos) {
             return Err(ActionError::OutOfBounds);
         }
 
-        let p_pos = match self.players.get(p_id) {
+        let player = match self.players.get(p_id) {
             None => {return Err(ActionError::InvalidPlayerID);},
-            Some(p) => &p.position
+            Some(p) => p
         };
 
-        if p_pos.get_grid_dist(t_pos) > PLAYER_SHOOT_DIST {
+        if player.position.get_grid_dist(t_pos) > player.range {
             return Err(ActionError::TargetTooFar);
         }
 
-        let target_player_id = match self.get_player_id_at_pos(t_pos) {
-            Some(i) => i,
-            None => {return Err(ActionError::NoTargetFound)}
+        if !self.has_line_of_sight(&player.position, t_pos) {
+            return Err(ActionError::NoLineOfSight);
+        }
+
+        if !self.rules.friendly_fire && player.position == *t_pos {
+            return Err(ActionError::FriendlyFire);
+        }
+
+        Ok(())
+    }
+
+    // Finds the tank at the target position for actions that give something to another tank
+    // The target has to be within the giving player's range
+    fn get_give_target(&self, p_id : &u8, t_pos : &BoardPos) -> Result<u8, ActionError> {
+        if !self.is_pos_in_bounds(t_pos) {
+            return Err(ActionError::OutOfBounds);
+        }
+
+        let player = match self.players.get(p_id) {
+            None => {return Err(ActionError::InvalidPlayerID);},
+            Some(p) => p
         };
 
-        let take_result = self.take_ap_from_player(p_id);
+        if player.position.get_grid_dist(t_pos) > player.range {
+            return Err(ActionError::TargetTooFar);
+        }
+
+        if !self.has_line_of_sight(&player.position, t_pos) {
+            return Err(ActionError::NoLineOfSight);
+        }
+
+        match self.get_player_id_at_pos(t_pos) {
+            Some(i) => Ok(i),
+            None => Err(ActionError::NoTargetFound)
+        }
+    }
+
+    fn apply_give_ap_action(&mut self, p_id : &u8, t_pos : &BoardPos) -> Result<Vec<GameEvent>, ActionError> {
+        let target_player_id = self.get_give_target(p_id, t_pos)?;
+
+        let take_result = self.take_ap_from_player(p_id, 1);
         match take_result {
             Ok(_) => {}
             Err(e) => match e {
@@ -234,24 +352,767 @@ impl Board {
             }
         }
 
-        self.players.get_mut(&target_player_id).unwrap().action_points += 1;
+        let target = self.players.get_mut(&target_player_id).unwrap();
+        target.action_points = target.action_points.saturating_add(1);
 
-        return Ok(());
+        return Ok(vec![GameEvent::APGiven { from: Some(*p_id), to: Some(target_player_id), amount: 1 }]);
     }
 
-    pub fn try_do_action(&mut self, action : &Action) -> Result<(), ActionError> {
+    // Moves one hitpoint from the player to the target tank, the giving tank dies if that was its last one
+    fn apply_give_hp_action(&mut self, p_id : &u8, t_pos : &BoardPos) -> Result<Vec<GameEvent>, ActionError> {
+        let target_player_id = self.get_give_target(p_id, t_pos)?;
+
+        if target_player_id == *p_id {
+            return Err(ActionError::NoTargetFound);
+        }
+
+        let target = self.players.get_mut(&target_player_id).unwrap();
+        target.hitpoints = target.hitpoints.saturating_add(1);
+        let mut events = vec![GameEvent::HPGiven { from: Some(*p_id), to: Some(target_player_id) }];
+        events.extend(self.damage_player(p_id));
+
+        Ok(events)
+    }
+
+    // Spends action points to increase the range of a tank by one
+    fn apply_upgrade_range_action(&mut self, p_id : &u8) -> Result<Vec<GameEvent>, ActionError> {
+        let take_result = self.take_ap_from_player(p_id, self.rules.range_upgrade_cost);
+        match take_result {
+            Ok(_) => {}
+            Err(e) => match e {
+                AccessError::CouldNotFindPlayer => {return Err(ActionError::InvalidPlayerID);}
+                AccessError::PlayerAPInsufficient => {return Err(ActionError::NotEnoughAP);}
+            }
+        }
+
+        let tank = self.players.get_mut(p_id).unwrap();
+        tank.range = tank.range.saturating_add(1);
+
+        Ok(vec![GameEvent::RangeUpgraded { tank: *p_id, range: tank.range }])
+    }
+
+    // Records a jury vote, each dead player gets one vote per AP period
+    fn apply_jury_vote_action(&mut self, voter : &u8, target : &u8) -> Result<Vec<GameEvent>, ActionError> {
+        if !self.graveyard.contains(voter) {
+            return Err(ActionError::NotInJury);
+        }
+
+        if !self.player_exists(target) {
+            return Err(ActionError::NoTargetFound);
+        }
+
+        if self.jury_votes.contains_key(voter) {
+            return Err(ActionError::AlreadyVoted);
+        }
+
+        self.jury_votes.insert(*voter, *target);
+
+        Ok(vec![GameEvent::JuryVoted { voter: *voter, target: *target }])
+    }
+
+    // Gets the living tanks that have received enough jury votes this AP period to earn a bonus AP
+    fn get_jury_winners(&self) -> Vec<u8> {
+        let mut tally: HashMap<u8, usize> = HashMap::new();
+        for target in self.jury_votes.values() {
+            *tally.entry(*target).or_insert(0) += 1;
+        }
+
+        tally.into_iter()
+            .filter(|(target, votes)| *votes >= usize::from(self.rules.jury_votes_needed) && self.player_exists(target))
+            .map(|(target, _)| target)
+            .collect()
+    }
+
+    // Gives every living tank one action point, plus one more for tanks the jury voted for
+    // This ends the AP period, so the jury's votes are cleared
+    fn apply_ap_tick_action(&mut self) -> Result<Vec<GameEvent>, ActionError> {
+        if self.rules.game_mode == GameMode::TurnBased {
+            return Err(ActionError::WrongGameMode);
+        }
+
+        // Tanks are gone through in order of ID so the events come out the same every time
+        let mut events: Vec<GameEvent> = Vec::new();
+        let mut winners = self.get_jury_winners();
+        winners.sort();
+        for target in winners {
+            let tank = self.players.get_mut(&target).unwrap();
+            tank.action_points = tank.action_points.saturating_add(1);
+            events.push(GameEvent::APGiven { from: None, to: Some(target), amount: 1 });
+        }
+        self.jury_votes.clear();
+
+        let mut ids: Vec<u8> = self.players.keys().copied().collect();
+        ids.sort();
+        for id in ids {
+            let tank = self.players.get_mut(&id).unwrap();
+            tank.action_points = tank.action_points.saturating_add(1);
+            events.push(GameEvent::APGiven { from: None, to: Some(id), amount: 1 });
+        }
+
+        Ok(events)
+    }
+
+    // Ends the acting tank's turn and starts the next one
+    fn apply_end_turn_action(&mut self, p_id : &u8) -> Result<Vec<GameEvent>, ActionError> {
+        if self.rules.game_mode != GameMode::TurnBased {
+            return Err(ActionError::WrongGameMode);
+        }
+
+        if !self.player_exists(p_id) {
+            return Err(ActionError::InvalidPlayerID);
+        }
+
+        Ok(self.start_next_turn().into_iter().collect())
+    }
+
+    // Hands the turn to the living tank with the next highest ID, going back round to the lowest once everyone has had a turn
+    // The tank starts its turn with the rules' AP budget, unspent AP from its last turn doesn't carry over
+    // Tanks the jury has voted for get their bonus AP at the start of their next turn, which uses up the votes for them
+    // A completed round ends the jury's voting period, votes for tanks that haven't had their bonus yet are kept until they do
+    pub fn start_next_turn(&mut self) -> Option<GameEvent> {
+        let mut ids: Vec<u8> = self.players.keys().copied().collect();
+        ids.sort();
+
+        let next = match ids.iter().find(|id| Some(**id) > self.active_player) {
+            Some(id) => *id,
+            None => {
+                let winners = self.get_jury_winners();
+                self.jury_votes.retain(|_, target| winners.contains(target));
+                match ids.first() {
+                    Some(id) => *id,
+                    None => {self.active_player = None; return None;}
+                }
+            }
+        };
+
+        let bonus = if self.get_jury_winners().contains(&next) {1} else {0};
+        if bonus > 0 {
+            self.jury_votes.retain(|_, target| *target != next);
+        }
+
+        let tank = self.players.get_mut(&next).unwrap();
+        tank.action_points = self.rules.turn_action_points.saturating_add(bonus);
+        self.active_player = Some(next);
+
+        Some(GameEvent::TurnStarted { tank: next, action_points: tank.action_points })
+    }
+
+    // Checks the action can be taken directly in the game's mode, the jury can vote at any time in every mode
+    // In turn based games only the tank whose turn it is can act, in simultaneous games tanks act through orders instead
+    pub fn check_game_mode(&self, action : &Action) -> Result<(), ActionError> {
+        let p_id = match action {
+            Action::JuryVote(_, _) => {return Ok(());},
+            _ => match action.get_acting_player() {
+                Some(p_id) => p_id,
+                None => {return Ok(());}
+            }
+        };
+
+        match self.rules.game_mode {
+            GameMode::RealTime => Ok(()),
+            GameMode::TurnBased if Some(p_id) != self.active_player => Err(ActionError::NotYourTurn),
+            GameMode::TurnBased => Ok(()),
+            GameMode::Simultaneous => Err(ActionError::WrongGameMode)
+        }
+    }
+
+    // Carries out every order given in a phase of a simultaneous game at once
+    // Moves go first, then shots, then everything else, with each tank's orders taken in the order they were given
+    fn apply_resolve_phase_action(&mut self, orders : &[Action]) -> Result<Vec<GameEvent>, ActionError> {
+        if self.rules.game_mode != GameMode::Simultaneous {
+            return Err(ActionError::WrongGameMode);
+        }
+
+        // Orders are limited to one move per tank when they are given, but a phase can also come from a replay
+        let mut ordered: HashSet<u8> = HashSet::new();
+        for order in orders.iter() {
+            if let Action::TankMove(p_id, _) = order {
+                if !ordered.insert(*p_id) {
+                    return Err(ActionError::TooManyMoves);
+                }
+            }
+        }
+
+        let mut results: Vec<Option<OrderResult>> = vec![None; orders.len()];
+        let mut events = self.resolve_move_orders(orders, &mut results);
+        events.extend(self.resolve_shoot_orders(orders, &mut results));
+
+        for (ind, order) in orders.iter().enumerate() {
+            let result = match order {
+                Action::TankMove(_, _) | Action::TankShoot(_, _) => {continue;},
+                Action::TankGiveAP(p_id, t_pos) => self.apply_give_ap_action(p_id, t_pos),
+                Action::TankGiveHP(p_id, t_pos) => self.apply_give_hp_action(p_id, t_pos),
+                Action::TankUpgradeRange(p_id) => self.apply_upgrade_range_action(p_id),
+                _ => Err(ActionError::WrongGameMode)
+            };
+            results[ind] = Some(match result {
+                Ok(order_events) => {events.extend(order_events); OrderResult::Applied},
+                Err(e) => OrderResult::Rejected(e)
+            });
+        }
+
+        let outcomes = orders.iter().zip(results)
+            .map(|(order, result)| OrderOutcome { order: order.clone(), result: result.unwrap() })
+            .collect();
+        self.last_resolution = Some(ResolutionReport { outcomes });
+
+        Ok(events)
+    }
+
+    // Checks a move order on its own, ignoring other tanks since they might move out of the way
+    fn check_move_order(&self, p_id : &u8, t_pos : &BoardPos) -> Result<(), ActionError> {
+        if !self.is_pos_in_bounds(t_pos) {
+            return Err(ActionError::OutOfBounds);
+        }
+
+        let player = match self.players.get(p_id) {
+            None => {return Err(ActionError::InvalidPlayerID);},
+            Some(p) => p
+        };
+
+        if player.position == *t_pos {
+            return Err(ActionError::SpaceOccupied);
+        }
+
+        if let Some(object) = self.objects.get(t_pos) {
+            if object.type_flags & INPASSABLE != 0 {
+                return Err(ActionError::SpaceOccupied);
+            }
+        }
+
+        if player.position.get_grid_dist(t_pos) > self.rules.move_distance {
+            return Err(ActionError::TargetTooFar);
+        }
+
+        if player.action_points == 0 {
+            return Err(ActionError::NotEnoughAP);
+        }
+
+        Ok(())
+    }
+
+    // Moves every tank with a legal move order at once
+    // Moves bounce if two tanks are heading for the same position, if two tanks are swapping places,
+    // or if the tank in the way is staying put. Bouncing can leave another tank in the way, so this repeats until nothing changes
+    fn resolve_move_orders(&mut self, orders : &[Action], results : &mut [Option<OrderResult>]) -> Vec<GameEvent> {
+        let mut moving: Vec<(usize, u8, BoardPos)> = Vec::new();
+        for (ind, order) in orders.iter().enumerate() {
+            if let Action::TankMove(p_id, t_pos) = order {
+                match self.check_move_order(p_id, t_pos) {
+                    Ok(()) => moving.push((ind, *p_id, t_pos.clone())),
+                    Err(e) => results[ind] = Some(OrderResult::Rejected(e))
+                }
+            }
+        }
+
+        loop {
+            let bounced: Vec<usize> = moving.iter()
+                .filter(|(_, p_id, t_pos)| {
+                    let shared = moving.iter().filter(|(_, _, other)| other == t_pos).count() > 1;
+                    let blocked = match self.get_player_id_at_pos(t_pos) {
+                        None => false,
+                        Some(occupant) => match moving.iter().find(|(_, id, _)| *id == occupant) {
+                            None => true,
+                            Some((_, _, occupant_target)) => *occupant_target == self.players[p_id].position
+                        }
+                    };
+                    shared || blocked
+                })
+                .map(|(ind, _, _)| *ind)
+                .collect();
+
+            if bounced.is_empty() {
+                break;
+            }
+
+            for ind in bounced {
+                results[ind] = Some(OrderResult::Bounced);
+                moving.retain(|(i, _, _)| *i != ind);
+            }
+        }
+
+        let mut events: Vec<GameEvent> = Vec::new();
+        let mut moved: Vec<(u8, BoardPos)> = Vec::new();
+        for (ind, p_id, t_pos) in moving.into_iter() {
+            let tank = self.players.get_mut(&p_id).unwrap();
+            tank.action_points = match tank.action_points.checked_sub(1) {
+                Some(ap) => ap,
+                None => {
+                    results[ind] = Some(OrderResult::Rejected(ActionError::NotEnoughAP));
+                    continue;
+                }
+            };
+            let from = std::mem::replace(&mut tank.position, t_pos.clone());
+            events.push(GameEvent::Moved { tank: p_id, from: Some(from), to: Some(t_pos.clone()) });
+            results[ind] = Some(OrderResult::Applied);
+            moved.push((p_id, t_pos));
+        }
+
+        for (p_id, t_pos) in moved.iter() {
+            events.extend(self.collect_pickup(p_id, t_pos));
+        }
+        events
+    }
+
+    // Fires every legal shot order at once, so a tank killed this phase still gets its own shots off
+    fn resolve_shoot_orders(&mut self, orders : &[Action], results : &mut [Option<OrderResult>]) -> Vec<GameEvent> {
+        let mut events: Vec<GameEvent> = Vec::new();
+        let mut targets: Vec<BoardPos> = Vec::new();
+        for (ind, order) in orders.iter().enumerate() {
+            if let Action::TankShoot(p_id, t_pos) = order {
+                let result = match self.check_shot(p_id, t_pos) {
+                    Ok(()) => match self.take_ap_from_player(p_id, 1) {
+                        Ok(_) => Ok(()),
+                        Err(AccessError::CouldNotFindPlayer) => Err(ActionError::InvalidPlayerID),
+                        Err(AccessError::PlayerAPInsufficient) => Err(ActionError::NotEnoughAP)
+                    },
+                    Err(e) => Err(e)
+                };
+
+                results[ind] = Some(match result {
+                    Ok(()) => {
+                        events.push(GameEvent::Shot { tank: Some(*p_id), target: t_pos.clone() });
+                        targets.push(t_pos.clone());
+                        OrderResult::Applied
+                    },
+                    Err(e) => OrderResult::Rejected(e)
+                });
+            }
+        }
+
+        for t_pos in targets {
+            events.extend(self.damage_things_at_board_pos(&t_pos));
+        }
+        events
+    }
+
+    // Places a heart pickup in an empty position
+    fn apply_spawn_heart_action(&mut self, pos : &BoardPos) -> Result<Vec<GameEvent>, ActionError> {
+        if !self.is_pos_in_bounds(pos) {
+            return Err(ActionError::OutOfBounds);
+        }
+
+        if !self.get_things_at_pos(pos).is_empty() {
+            return Err(ActionError::SpaceOccupied);
+        }
+
+        self.objects.insert(pos.clone(), BoardObject { type_flags: board_object::HEART });
+
+        Ok(vec![GameEvent::HeartSpawned { pos: pos.clone() }])
+    }
+
+    // Checks if a tank at the viewing position can see the target position
+    fn can_see_pos(&self, from : &BoardPos, pos : &BoardPos) -> bool {
+        from.get_grid_dist(pos) <= self.rules.sight_distance && self.has_line_of_sight(from, pos)
+    }
+
+    // Gets every in bounds position a tank at the given position can see
+    fn get_visible_positions(&self, from : &BoardPos) -> Vec<BoardPos> {
+        let mut out: Vec<BoardPos> = Vec::new();
+        let (min_x, max_x) = (from.0.saturating_sub(self.rules.sight_distance), min(from.0.saturating_add(self.rules.sight_distance), self.size_x.saturating_sub(1)));
+        let (min_y, max_y) = (from.1.saturating_sub(self.rules.sight_distance), min(from.1.saturating_add(self.rules.sight_distance), self.size_y.saturating_sub(1)));
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                let pos = BoardPos(x, y);
+                if self.is_pos_in_bounds(&pos) && self.can_see_pos(from, &pos) {
+                    out.push(pos);
+                }
+            }
+        }
+        out
+    }
+
+    // Updates what every living player remembers with what they can currently see
+    // Dead players lose their memory since they see the whole board from the jury
+    pub fn update_memories(&mut self) {
+        self.memories.retain(|p_id, _| self.players.contains_key(p_id));
+
+        let viewers: Vec<(u8, BoardPos)> = self.players.iter().map(|(id, tank)| (*id, tank.position.clone())).collect();
+        for (viewer, viewer_pos) in viewers {
+            let visible = self.get_visible_positions(&viewer_pos);
+            let mut memory = self.memories.remove(&viewer).unwrap_or_default();
+
+            for pos in visible.iter() {
+                match self.objects.get(pos) {
+                    Some(object) => {memory.known_objects.insert(pos.clone(), object.clone());},
+                    None => {memory.known_objects.remove(pos);}
+                }
+            }
+
+            for (id, tank) in self.players.iter() {
+                if *id != viewer && self.can_see_pos(&viewer_pos, &tank.position) {
+                    memory.last_seen_tanks.insert(*id, tank.position.clone());
+                }
+            }
+            memory.last_seen_tanks.retain(|id, _| self.players.contains_key(id));
+
+            self.memories.insert(viewer, memory);
+        }
+    }
+
+    // Builds the view of the board the given player is allowed to see
+    // Living players see what is in sight of their tank plus what they remember, the jury sees everything
+    pub fn view_for(&self, p_id : &u8) -> Option<BoardView> {
+        if self.graveyard.contains(p_id) {
+            return Some(BoardView {
+                size_x: self.size_x,
+                size_y: self.size_y,
+                visible_tanks: self.players.clone(),
+                last_seen_tanks: HashMap::new(),
+                known_objects: self.objects.clone(),
+                graveyard: self.graveyard.clone(),
+                active_player: self.active_player,
+                last_resolution: self.last_resolution.clone(),
+                phase_deadline: None
+            });
+        }
+
+        let viewer_pos = &self.players.get(p_id)?.position;
+        let memory = self.memories.get(p_id).cloned().unwrap_or_default();
+
+        let visible_tanks: HashMap<u8, PlayerTank> = self.players.iter()
+            .filter(|(id, tank)| *id == p_id || self.can_see_pos(viewer_pos, &tank.position))
+            .map(|(id, tank)| (*id, tank.clone()))
+            .collect();
+
+        let last_seen_tanks = memory.last_seen_tanks.into_iter()
+            .filter(|(id, _)| !visible_tanks.contains_key(id))
+            .collect();
+
+        Some(BoardView {
+            size_x: self.size_x,
+            size_y: self.size_y,
+            visible_tanks,
+            last_seen_tanks,
+            known_objects: memory.known_objects,
+            graveyard: self.graveyard.clone(),
+            active_player: self.active_player,
+            last_resolution: self.last_resolution.as_ref().map(|report| ResolutionReport {
+                outcomes: report.outcomes.iter()
+                    .filter(|outcome| outcome.order.get_acting_player() == Some(*p_id))
+                    .cloned()
+                    .collect()
+            }),
+            phase_deadline: None
+        })
+    }
+
+    // Picks out the events the given player is allowed to know about, checked against the board after the events happened
+    // Living players hear about their own tank and what is in sight of it, the jury hears everything
+    pub fn filter_events_for(&self, p_id : &u8, events : &[GameEvent]) -> Vec<GameEvent> {
+        if self.graveyard.contains(p_id) {
+            return events.to_vec();
+        }
+
+        let viewer_pos = match self.players.get(p_id) {
+            Some(tank) => &tank.position,
+            None => {return Vec::new();}
+        };
+        let sees_pos = |pos : &BoardPos| self.can_see_pos(viewer_pos, pos);
+        let sees_tank = |id : &u8| id == p_id || self.players.get(id).is_some_and(|tank| sees_pos(&tank.position));
+
+        // Parts of an event the player couldn't see are left out, the event is dropped if nothing is left
+        let shown_pos = |pos : &Option<BoardPos>| pos.clone().filter(|p| sees_pos(p));
+        let shown_tank = |id : &Option<u8>| id.filter(|t| sees_tank(t));
+
+        events.iter()
+            .filter_map(|event| match event {
+                GameEvent::Moved { tank, .. } if tank == p_id => Some(event.clone()),
+                GameEvent::Moved { tank, from, to } => {
+                    let (from, to) = (shown_pos(from), shown_pos(to));
+                    (from.is_some() || to.is_some()).then_some(GameEvent::Moved { tank: *tank, from, to })
+                },
+                GameEvent::Shot { tank, target } => {
+                    let tank = shown_tank(tank);
+                    (tank.is_some() || sees_pos(target)).then_some(GameEvent::Shot { tank, target: target.clone() })
+                },
+                GameEvent::APGiven { from, to, amount } => {
+                    let (from, to) = (shown_tank(from), shown_tank(to));
+                    (from.is_some() || to.is_some()).then_some(GameEvent::APGiven { from, to, amount: *amount })
+                },
+                GameEvent::HPGiven { from, to } => {
+                    let (from, to) = (shown_tank(from), shown_tank(to));
+                    (from.is_some() || to.is_some()).then_some(GameEvent::HPGiven { from, to })
+                },
+                GameEvent::Hit { tank, .. } | GameEvent::RangeUpgraded { tank, .. } => sees_tank(tank).then(|| event.clone()),
+                GameEvent::ObjectDestroyed { pos } | GameEvent::HeartSpawned { pos } => sees_pos(pos).then(|| event.clone()),
+                GameEvent::PickupCollected { tank, pos } => (sees_tank(tank) || sees_pos(pos)).then(|| event.clone()),
+                GameEvent::JuryVoted { .. } => None,
+                GameEvent::Killed { .. } | GameEvent::TurnStarted { .. } | GameEvent::Won { .. } | GameEvent::Draw => Some(event.clone())
+            })
+            .collect()
+    }
+
+    // Checks if the player is allowed to know what an action was, checked against the board on either side of it
+    // Tank actions are seen while the acting tank is in sight, other actions give away no more than their events do
+    pub fn can_see_action(&self, p_id : &u8, action : &Action) -> bool {
+        if self.graveyard.contains(p_id) {
+            return true;
+        }
+
+        let viewer_pos = match self.players.get(p_id) {
+            Some(tank) => &tank.position,
+            None => {return false;}
+        };
+
         match action {
+            Action::TankMove(actor, _) | Action::TankShoot(actor, _) | Action::TankGiveAP(actor, _) | Action::TankGiveHP(actor, _) | Action::TankUpgradeRange(actor) => {
+                // A tank that died making the move is gone from the board, but its death is seen by everyone
+                actor == p_id || self.players.get(actor).is_none_or(|tank| self.can_see_pos(viewer_pos, &tank.position))
+            },
+            Action::JuryVote(voter, _) => voter == p_id,
+            Action::SpawnHeart(pos) => self.can_see_pos(viewer_pos, pos),
+            Action::APTick | Action::EndTurn(_) => true,
+            Action::ResolvePhase(_) => false // Holds the sealed orders of every tank
+        }
+    }
+
+    // Applies an action to the board, returning everything that happened because of it
+    pub fn try_do_action(&mut self, action : &Action) -> Result<Vec<GameEvent>, ActionError> {
+        self.check_game_mode(action)?;
+        let dead_before = self.graveyard.len();
+        let state_before = self.get_game_state();
+
+        let mut events = match action {
             Action::TankGiveAP(p_id, t_pos) => self.apply_give_ap_action(&p_id, &t_pos),
             Action::TankMove(p_id, t_pos) => self.apply_move_action(&p_id, &t_pos),
-            Action::TankShoot(p_id, t_pos) => self.apply_shoot_action(&p_id, &t_pos)
+            Action::TankShoot(p_id, t_pos) => self.apply_shoot_action(&p_id, &t_pos),
+            Action::TankGiveHP(p_id, t_pos) => self.apply_give_hp_action(p_id, t_pos),
+            Action::TankUpgradeRange(p_id) => self.apply_upgrade_range_action(p_id),
+            Action::JuryVote(voter, target) => self.apply_jury_vote_action(voter, target),
+            Action::APTick => self.apply_ap_tick_action(),
+            Action::SpawnHeart(pos) => self.apply_spawn_heart_action(pos),
+            Action::EndTurn(p_id) => self.apply_end_turn_action(p_id),
+            Action::ResolvePhase(orders) => self.apply_resolve_phase_action(orders)
+        }?;
+
+        let eliminated = self.graveyard[dead_before..].to_vec();
+        if !eliminated.is_empty() {
+            self.eliminations.push(eliminated);
         }
+
+        // A tank can die on its own turn by giving away its last hitpoint, so move the turn on
+        if let Some(active) = self.active_player {
+            if !self.player_exists(&active) {
+                events.extend(self.start_next_turn());
+            }
+        }
+        self.update_memories();
+
+        let state_after = self.get_game_state();
+        if state_after != state_before {
+            match state_after {
+                GameState::GameWon(tank) => events.push(GameEvent::Won { tank }),
+                GameState::Draw => events.push(GameEvent::Draw),
+                _ => {}
+            }
+        }
+        Ok(events)
     }
 
+    // The game is won once only one tank is left alive, if the last tanks all die together it is a draw
     pub fn get_game_state(&self) -> GameState {
-        if self.players.len() == 1 {
-            // Hack to get the only value of a size one iterrator
-            let _ = self.players.keys().map(|id| return GameState::GameWon(*id));
+        match self.players.keys().next() {
+            None => GameState::Draw,
+            Some(id) if self.players.len() == 1 => GameState::GameWon(*id),
+            Some(_) => GameState::InProgress
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn turn_based_board_with_jury() -> Board {
+        let rules = GameRules { game_mode: GameMode::TurnBased, ..Default::default() };
+        let players = (0..3).map(|id| (id, PlayerTank::new(BoardPos(u16::from(id) * 2, 0), &rules))).collect();
+        Board { size_x: 6, size_y: 1, players, graveyard: vec![3, 4, 5], rules, ..Default::default() }
+    }
+
+    #[test]
+    fn jury_bonus_carries_over_the_end_of_the_round() {
+        let mut board = turn_based_board_with_jury();
+        board.active_player = Some(2);
+
+        // Votes for tank 1 given during tank 2's turn, after tank 1 has already had its turn this round
+        for voter in 3..6 {
+            board.try_do_action(&Action::JuryVote(voter, 1)).unwrap();
+        }
+
+        assert_eq!(board.start_next_turn(), Some(GameEvent::TurnStarted { tank: 0, action_points: 3 }));
+        assert_eq!(board.start_next_turn(), Some(GameEvent::TurnStarted { tank: 1, action_points: 4 }));
+        assert_eq!(board.start_next_turn(), Some(GameEvent::TurnStarted { tank: 2, action_points: 3 }));
+
+        // The bonus is only given once
+        assert_eq!(board.start_next_turn(), Some(GameEvent::TurnStarted { tank: 0, action_points: 3 }));
+        assert_eq!(board.start_next_turn(), Some(GameEvent::TurnStarted { tank: 1, action_points: 3 }));
+    }
+
+    #[test]
+    fn votes_short_of_a_bonus_end_with_the_round() {
+        let mut board = turn_based_board_with_jury();
+        board.active_player = Some(0);
+        board.try_do_action(&Action::JuryVote(3, 2)).unwrap();
+        board.try_do_action(&Action::JuryVote(4, 2)).unwrap();
+
+        board.start_next_turn();
+        board.start_next_turn();
+        board.start_next_turn();
+        assert!(board.jury_votes.is_empty());
+    }
+
+    // A simultaneous game with a tank at each of the given positions, tank n is at index n
+    fn simultaneous_board(size_x : u16, positions : &[BoardPos]) -> Board {
+        let rules = GameRules { game_mode: GameMode::Simultaneous, starting_action_points: 3, ..Default::default() };
+        let players = positions.iter().enumerate().map(|(id, pos)| (id as u8, PlayerTank::new(pos.clone(), &rules))).collect();
+        Board { size_x, size_y: 1, players, rules, ..Default::default() }
+    }
+
+    fn resolve(board : &mut Board, orders : Vec<Action>) -> Vec<OrderResult> {
+        board.try_do_action(&Action::ResolvePhase(orders)).unwrap();
+        board.last_resolution.clone().unwrap().outcomes.into_iter().map(|o| o.result).collect()
+    }
+
+    fn position_of(board : &Board, p_id : u8) -> BoardPos {
+        board.players[&p_id].position.clone()
+    }
+
+    #[test]
+    fn moves_to_the_same_position_both_bounce() {
+        let mut board = simultaneous_board(3, &[BoardPos(0, 0), BoardPos(2, 0)]);
+        let results = resolve(&mut board, vec![Action::TankMove(0, BoardPos(1, 0)), Action::TankMove(1, BoardPos(1, 0))]);
+
+        assert!(matches!(results[..], [OrderResult::Bounced, OrderResult::Bounced]));
+        assert_eq!(position_of(&board, 0), BoardPos(0, 0));
+        assert_eq!(position_of(&board, 1), BoardPos(2, 0));
+        assert_eq!(board.players[&0].action_points, 3);
+    }
+
+    #[test]
+    fn tanks_swapping_places_bounce() {
+        let mut board = simultaneous_board(2, &[BoardPos(0, 0), BoardPos(1, 0)]);
+        let results = resolve(&mut board, vec![Action::TankMove(0, BoardPos(1, 0)), Action::TankMove(1, BoardPos(0, 0))]);
+
+        assert!(matches!(results[..], [OrderResult::Bounced, OrderResult::Bounced]));
+        assert_eq!(position_of(&board, 0), BoardPos(0, 0));
+        assert_eq!(position_of(&board, 1), BoardPos(1, 0));
+    }
+
+    #[test]
+    fn chain_follows_a_tank_moving_out_of_the_way() {
+        let mut board = simultaneous_board(4, &[BoardPos(0, 0), BoardPos(1, 0), BoardPos(2, 0)]);
+        let results = resolve(&mut board, vec![
+            Action::TankMove(0, BoardPos(1, 0)),
+            Action::TankMove(1, BoardPos(2, 0)),
+            Action::TankMove(2, BoardPos(3, 0))
+        ]);
+
+        assert!(matches!(results[..], [OrderResult::Applied, OrderResult::Applied, OrderResult::Applied]));
+        assert_eq!(position_of(&board, 0), BoardPos(1, 0));
+        assert_eq!(position_of(&board, 1), BoardPos(2, 0));
+        assert_eq!(position_of(&board, 2), BoardPos(3, 0));
+        assert_eq!(board.players[&0].action_points, 2);
+    }
+
+    #[test]
+    fn chain_bounces_behind_a_tank_staying_put() {
+        let mut board = simultaneous_board(3, &[BoardPos(0, 0), BoardPos(1, 0), BoardPos(2, 0)]);
+        let results = resolve(&mut board, vec![Action::TankMove(0, BoardPos(1, 0)), Action::TankMove(1, BoardPos(2, 0))]);
+
+        assert!(matches!(results[..], [OrderResult::Bounced, OrderResult::Bounced]));
+        assert_eq!(position_of(&board, 0), BoardPos(0, 0));
+        assert_eq!(position_of(&board, 1), BoardPos(1, 0));
+    }
+
+    #[test]
+    fn illegal_orders_are_rejected_without_stopping_the_rest() {
+        let mut board = simultaneous_board(4, &[BoardPos(0, 0), BoardPos(3, 0)]);
+        let results = resolve(&mut board, vec![Action::TankMove(0, BoardPos(2, 0)), Action::TankMove(1, BoardPos(2, 0))]);
+
+        assert!(matches!(results[..], [OrderResult::Rejected(ActionError::TargetTooFar), OrderResult::Applied]));
+        assert_eq!(position_of(&board, 1), BoardPos(2, 0));
+    }
+
+    #[test]
+    fn phases_with_two_moves_for_a_tank_are_refused() {
+        let mut board = simultaneous_board(4, &[BoardPos(0, 0)]);
+        board.players.get_mut(&0).unwrap().action_points = 1;
+        let result = board.try_do_action(&Action::ResolvePhase(vec![Action::TankMove(0, BoardPos(1, 0)), Action::TankMove(0, BoardPos(2, 0))]));
+
+        assert!(matches!(result, Err(ActionError::TooManyMoves)));
+        assert_eq!(position_of(&board, 0), BoardPos(0, 0));
+        assert_eq!(board.players[&0].action_points, 1);
+    }
+
+    #[test]
+    fn tanks_that_kill_each_other_draw() {
+        let mut board = simultaneous_board(3, &[BoardPos(0, 0), BoardPos(2, 0)]);
+        for tank in board.players.values_mut() {
+            tank.hitpoints = 1;
         }
-        return GameState::InProgress;
+
+        let events = board.try_do_action(&Action::ResolvePhase(vec![Action::TankShoot(0, BoardPos(2, 0)), Action::TankShoot(1, BoardPos(0, 0))])).unwrap();
+
+        assert!(board.players.is_empty());
+        assert_eq!(board.get_game_state(), GameState::Draw);
+        assert_eq!(board.eliminations, vec![vec![1, 0]]);
+        assert_eq!(events.last(), Some(&GameEvent::Draw));
+    }
+
+    #[test]
+    fn tank_actions_outside_orders_are_rejected() {
+        let mut board = simultaneous_board(3, &[BoardPos(0, 0), BoardPos(2, 0)]);
+        assert!(matches!(board.try_do_action(&Action::TankMove(0, BoardPos(1, 0))), Err(ActionError::WrongGameMode)));
+    }
+
+    #[test]
+    fn tank_moving_into_forest_hides_where_it_went() {
+        let rules = GameRules { starting_action_points: 1, sight_distance: 4, ..Default::default() };
+        let players = [(0, BoardPos(0, 0)), (1, BoardPos(2, 0))].into_iter().map(|(id, pos)| (id, PlayerTank::new(pos, &rules))).collect();
+        let mut board = Board { size_x: 5, size_y: 1, players, rules, ..Default::default() };
+        board.objects.insert(BoardPos(2, 0), BoardObject { type_flags: board_object::FOREST });
+        board.objects.insert(BoardPos(3, 0), BoardObject { type_flags: board_object::FOREST });
+
+        let events = board.try_do_action(&Action::TankMove(1, BoardPos(3, 0))).unwrap();
+
+        assert_eq!(board.filter_events_for(&0, &events), vec![GameEvent::Moved { tank: 1, from: Some(BoardPos(2, 0)), to: None }]);
+        assert_eq!(board.filter_events_for(&1, &events), events);
+    }
+
+    #[test]
+    fn actions_are_hidden_only_when_the_acting_tank_is() {
+        let rules = GameRules { sight_distance: 2, ..Default::default() };
+        let players = [(0, BoardPos(0, 0)), (1, BoardPos(1, 0)), (2, BoardPos(4, 0))].into_iter().map(|(id, pos)| (id, PlayerTank::new(pos, &rules))).collect();
+        let board = Board { size_x: 5, size_y: 1, players, rules, ..Default::default() };
+
+        assert!(board.can_see_action(&0, &Action::TankShoot(1, BoardPos(3, 0))));
+        assert!(!board.can_see_action(&0, &Action::TankShoot(2, BoardPos(3, 0))));
+        assert!(board.can_see_action(&0, &Action::APTick));
+        assert!(!board.can_see_action(&0, &Action::SpawnHeart(BoardPos(3, 0))));
+    }
+
+    #[test]
+    fn line_of_sight_is_symmetric() {
+        let mut board = Board { size_x: 10, size_y: 10, ..Default::default() };
+        board.objects.insert(BoardPos(4, 5), BoardObject { type_flags: board_object::FOREST });
+        board.objects.insert(BoardPos(7, 2), BoardObject { type_flags: board_object::FOREST });
+
+        let positions: Vec<BoardPos> = (0..10).flat_map(|x| (0..10).map(move |y| BoardPos(x, y))).collect();
+        for a in positions.iter() {
+            for b in positions.iter() {
+                assert_eq!(board.has_line_of_sight(a, b), board.has_line_of_sight(b, a), "{:?} and {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn forest_blocks_line_of_sight() {
+        let mut board = Board { size_x: 5, size_y: 1, ..Default::default() };
+        board.objects.insert(BoardPos(2, 0), BoardObject { type_flags: board_object::FOREST });
+
+        assert!(!board.has_line_of_sight(&BoardPos(0, 0), &BoardPos(4, 0)));
+        assert!(board.has_line_of_sight(&BoardPos(0, 0), &BoardPos(2, 0)));
+        assert!(board.has_line_of_sight(&BoardPos(0, 0), &BoardPos(1, 0)));
     }
-}
\ No newline at end of file
+}
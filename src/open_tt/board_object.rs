@@ -1,18 +1,24 @@
+use serde::{Deserialize, Serialize};
+
 use super::BoardPos;
 
 // Flags for object properties
 pub const INPASSABLE :u8= 0b00000001;
 pub const BLOCK_SIGHT :u8= 0b00000010;
 pub const DESTRUCTABLE :u8= 0b00000100;
+pub const PICKUP :u8= 0b00001000; // Collected by a tank moving onto it
+pub const RESTORES_HP :u8= 0b00010000;
+pub const KNOWN_FLAGS :u8= INPASSABLE + BLOCK_SIGHT + DESTRUCTABLE + PICKUP + RESTORES_HP;
 
 
 // Some basic object types
 pub const FOREST :u8= DESTRUCTABLE + BLOCK_SIGHT;
 pub const WATER :u8= INPASSABLE;
+pub const HEART :u8= PICKUP + RESTORES_HP;
 
 
 // Represents a semi-static board object
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BoardObject {
     pub type_flags : u8
 }
\ No newline at end of file
@@ -1,20 +1,66 @@
 use super::*;
 use board;
-use rand::{thread_rng, seq::SliceRandom};
+use std::ops::Range;
+
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 
 // Implementation file for Game Struct
-enum BoardReconstructionError {
+#[derive(Debug)]
+pub enum BoardReconstructionError {
     TurnOutOfBounds,
-    MoveError(u16, ActionError)
+    MoveError(usize, ActionError) // The index of the move that couldn't be replayed and why
+}
+
+#[derive(Debug)]
+pub enum GameCreationError {
+    NotEnoughSpawnpoints
 }
 
-enum MoveError {
+#[derive(Debug, Serialize)]
+pub enum MoveError {
     ActionError(ActionError),
     GameIsOver
 }
 
+// A stretch of a game's moves along with the board they were made on, copied out so it can be gone over without holding on to the game
+pub struct GameSection {
+    board: Board, // Board from the nearest snapshot at or before the first move asked for
+    first_move: usize, // Number of the first move in moves, the board is from just before it
+    start: usize, // Number of the first move asked for, the moves before it only bring the board up to date
+    moves: Vec<Action>,
+    events: Vec<Vec<GameEvent>>
+}
+
+impl GameSection {
+    // Gets what the player was allowed to see of each move asked for, whether they can know what the action was and its events filtered for them
+    pub fn get_seen_moves_for(&self, p_id: &u8) -> Result<Vec<(bool, Vec<GameEvent>)>, BoardReconstructionError> {
+        let mut board = self.board.clone();
+        let mut out: Vec<(bool, Vec<GameEvent>)> = Vec::new();
+
+        for (ind, action) in self.moves.iter().enumerate() {
+            let move_num = self.first_move + ind;
+            let seen_before = board.can_see_action(p_id, action);
+            if let Err(e) = board.try_do_action(action) {
+                return Err(BoardReconstructionError::MoveError(move_num, e));
+            }
+            if move_num >= self.start {
+                out.push((seen_before && board.can_see_action(p_id, action), board.filter_events_for(p_id, &self.events[ind])));
+            }
+        }
+
+        Ok(out)
+    }
+
+    // Gets the events of each move asked for, filtered by what the player was allowed to see just after the move
+    pub fn get_events_for(&self, p_id: &u8) -> Result<Vec<Vec<GameEvent>>, BoardReconstructionError> {
+        self.get_seen_moves_for(p_id).map(|moves| moves.into_iter().map(|(_, events)| events).collect())
+    }
+}
+
+
 impl Game {
-    fn new(player_count : &u8, map : &Map) -> Game {
+    pub fn new(player_count : &u8, map : &Map, rules : &GameRules, seed : u64) -> Result<Game, GameCreationError> {
         let obstacles : HashMap<BoardPos, BoardObject> = HashMap::from_iter(
             map.items.iter()
             .map(|i| match i {
@@ -22,69 +68,211 @@ impl Game {
             })
         );
 
-        let mut spawnpoints : Vec<BoardPos> = Vec::new();
-
-        for x in (0..map.size_x) {
-            for y in (0..map.size_y) {
-                if match obstacles.get(&BoardPos(x, y)) {
-                    None => true,
-                    Some(o) => o.type_flags & INPASSABLE != 0
-                } {
-                    spawnpoints.push(BoardPos(x, y));
-                }
-            }
-        }
-
+        let spawnpoints = map.pick_spawns(*player_count, &mut ChaCha8Rng::seed_from_u64(seed))?;
         let mut players : HashMap<u8, PlayerTank> = HashMap::new();
-        spawnpoints.shuffle(&mut thread_rng());
-
-        for id in 0..*player_count {
-            players.insert(id, PlayerTank{position: spawnpoints.pop().unwrap(), ..Default::default()});
-        } 
+        for (id, pos) in (0..*player_count).zip(spawnpoints) {
+            players.insert(id, PlayerTank::new(pos, rules));
+        }
 
-        let board = Board {
+        let mut board = Board {
             size_x: map.size_x,
             size_y: map.size_y,
             players: players,
-            objects: obstacles
+            objects: obstacles,
+            rules: rules.clone(),
+            ..Default::default()
         };
+        if rules.game_mode == GameMode::TurnBased {
+            let _ = board.start_next_turn();
+        }
+        board.update_memories();
+
+        let game_state = board.get_game_state();
+        Ok(Self { map: map.clone(), starting_board: board.clone(), current_board: board, game_state, seed, ..Default::default() })
+    }
 
-        Self { starting_board: board.clone(), current_board: board, ..Default::default() }
+    // Rebuilds a game from its starting board and move log by replaying every move
+    pub fn from_log(map: Map, starting_board: Board, moves: Vec<Action>, seed: u64) -> Result<Game, BoardReconstructionError> {
+        let mut game = Game { map, starting_board: starting_board.clone(), current_board: starting_board, seed, ..Default::default() };
+        game.replay_moves(moves)?;
+        Ok(game)
+    }
+
+    // Rebuilds a game from its starting board, stored snapshots and move log
+    // Only the moves after the latest snapshot are replayed, if the snapshots don't line up with the log every move is replayed instead
+    // Events are only rebuilt for replayed moves, the moves before the snapshot get empty event lists
+    pub fn from_snapshots(map: Map, starting_board: Board, snapshots: Vec<Board>, mut moves: Vec<Action>, seed: u64) -> Result<Game, BoardReconstructionError> {
+        if snapshots.len() != moves.len() / SNAPSHOT_INTERVAL {
+            return Game::from_log(map, starting_board, moves, seed);
+        }
+
+        let replay_from = snapshots.len() * SNAPSHOT_INTERVAL;
+        let tail = moves.split_off(replay_from);
+        let current_board = snapshots.last().cloned().unwrap_or_else(|| starting_board.clone());
+        let game_state = current_board.get_game_state();
+
+        let events = vec![Vec::new(); moves.len()];
+        let mut game = Game { map, starting_board, current_board, moves, events, game_state, snapshots, seed, ..Default::default() };
+        game.replay_moves(tail)?;
+        Ok(game)
+    }
+
+    // Replays stored moves onto the end of the game, unlike do_action this doesn't refuse moves once the game is over
+    fn replay_moves(&mut self, moves: Vec<Action>) -> Result<(), BoardReconstructionError> {
+        for action in moves {
+            let t_ind = self.moves.len();
+            if let Err(e) = self.apply_action(action) {
+                return Err(BoardReconstructionError::MoveError(t_ind, e));
+            }
+        }
+        Ok(())
     }
 
     // Reconstructs the board state after a given number of turns
-    fn get_board_at_turn(&self, turn_num: u16) -> Result<Board, BoardReconstructionError> {
-        if usize::from(turn_num) >= self.moves.len() {
+    // Starts from the nearest snapshot at or before the turn, so at most SNAPSHOT_INTERVAL moves are replayed
+    pub fn get_board_at_turn(&self, turn_num: usize) -> Result<Board, BoardReconstructionError> {
+        if turn_num > self.moves.len() {
             return Err(BoardReconstructionError::TurnOutOfBounds)
         }
 
+        let snapshot_ind = min(turn_num / SNAPSHOT_INTERVAL, self.snapshots.len());
+        let mut new_board = match snapshot_ind {
+            0 => self.starting_board.clone(),
+            i => self.snapshots[i - 1].clone()
+        };
 
-        let mut new_board = self.starting_board.clone();
+        for t_ind in (snapshot_ind * SNAPSHOT_INTERVAL)..turn_num {
+            let action = &self.moves[t_ind];
+            if let Err(e) = new_board.try_do_action(action) {
+                return Err(BoardReconstructionError::MoveError(t_ind, e));
+            }
+        }
 
-        for t_ind in 0..turn_num {
-            let action = self.moves.get(usize::from(turn_num)).unwrap(); 
-            let result = new_board.try_do_action(action);
-            match result {
-                Err(e) => {return Err(BoardReconstructionError::MoveError(t_ind, e));},
-                _ => {}
-            };
+        Ok(new_board)
+    }
+
+    // Copies out the given moves along with everything needed to go over them, starting from the nearest snapshot before them
+    pub fn get_section(&self, move_nums: Range<usize>) -> Result<GameSection, BoardReconstructionError> {
+        if move_nums.start > move_nums.end || move_nums.end > self.moves.len() {
+            return Err(BoardReconstructionError::TurnOutOfBounds);
+        }
+
+        let snapshot_ind = min(move_nums.start / SNAPSHOT_INTERVAL, self.snapshots.len());
+        let first_move = snapshot_ind * SNAPSHOT_INTERVAL;
+        Ok(GameSection {
+            board: match snapshot_ind {
+                0 => self.starting_board.clone(),
+                i => self.snapshots[i - 1].clone()
+            },
+            first_move,
+            start: move_nums.start,
+            moves: self.moves[first_move..move_nums.end].to_vec(),
+            events: self.events[first_move..move_nums.end].to_vec()
+        })
+    }
+
+    // Applies an action to the current board and adds it and its events to the log, taking a snapshot if one is due
+    fn apply_action(&mut self, action: Action) -> Result<Vec<GameEvent>, ActionError> {
+        let events = self.current_board.try_do_action(&action)?;
+        self.moves.push(action);
+        self.events.push(events.clone());
+
+        if self.moves.len().is_multiple_of(SNAPSHOT_INTERVAL) {
+            self.snapshots.push(self.current_board.clone());
         }
 
-        return Ok(new_board);
+        self.game_state = self.current_board.get_game_state();
+        Ok(events)
     }
 
-    fn do_action(&mut self, action: Action) -> Result<(), MoveError>{
+    pub fn do_action(&mut self, action: Action) -> Result<Vec<GameEvent>, MoveError>{
         if self.game_state != GameState::InProgress {
             return Err(MoveError::GameIsOver);
         }
-        let result = self.current_board.try_do_action(&action);
-        match result {
-            Err(e) => {return Err(MoveError::ActionError(e));},
-            Ok(()) => {
-                self.moves.push(action);
+
+        let ends_phase = matches!(action, Action::ResolvePhase(_));
+        let events = self.apply_action(action).map_err(MoveError::ActionError)?;
+        if ends_phase {
+            self.pending_orders.clear();
+        }
+        Ok(events)
+    }
+
+    // Replaces a tank's sealed orders for the current phase of a simultaneous game, an empty list withdraws them
+    // Orders are only checked for who gives them here, whether they can be carried out is decided when the phase is resolved
+    pub fn set_orders(&mut self, p_id: u8, orders: Vec<Action>) -> Result<(), MoveError> {
+        if self.game_state != GameState::InProgress {
+            return Err(MoveError::GameIsOver);
+        }
+
+        if self.current_board.rules.game_mode != GameMode::Simultaneous {
+            return Err(MoveError::ActionError(ActionError::WrongGameMode));
+        }
+
+        if !self.current_board.players.contains_key(&p_id) {
+            return Err(MoveError::ActionError(ActionError::InvalidPlayerID));
+        }
+
+        let mut moves = 0;
+        for order in orders.iter() {
+            match order {
+                Action::TankMove(_, _) => {moves += 1;},
+                Action::TankShoot(_, _) | Action::TankGiveAP(_, _) | Action::TankGiveHP(_, _) | Action::TankUpgradeRange(_) => {},
+                _ => {return Err(MoveError::ActionError(ActionError::WrongGameMode));}
             }
+            if order.get_acting_player() != Some(p_id) {
+                return Err(MoveError::ActionError(ActionError::InvalidPlayerID));
+            }
+        }
+        if moves > 1 {
+            return Err(MoveError::ActionError(ActionError::TooManyMoves));
+        }
+
+        if orders.is_empty() {
+            self.pending_orders.remove(&p_id);
+        } else {
+            self.pending_orders.insert(p_id, orders);
+        }
+        Ok(())
+    }
+
+    // Gets the action that resolves the current phase, with every tank's orders in order of tank ID
+    // Phases nobody gave orders in are skipped, so there is nothing to resolve
+    pub fn get_phase_resolution(&self) -> Option<Action> {
+        if self.current_board.rules.game_mode != GameMode::Simultaneous || self.pending_orders.is_empty() {
+            return None;
+        }
+
+        let mut ids: Vec<&u8> = self.pending_orders.keys().collect();
+        ids.sort();
+        Some(Action::ResolvePhase(ids.into_iter().flat_map(|id| self.pending_orders[id].clone()).collect()))
+    }
+
+    // Gets the final standings once the game is over, the winner first then the rest in reverse order of elimination
+    // In a draw the tanks eliminated last share first place
+    pub fn get_results(&self) -> Option<GameResults> {
+        let mut places: Vec<Vec<u8>> = match self.game_state {
+            GameState::GameWon(winner) => vec![vec![winner]],
+            GameState::Draw => Vec::new(),
+            _ => {return None;}
         };
-        self.game_state = self.current_board.get_game_state();
-        return Ok(());
+
+        places.extend(self.current_board.eliminations.iter().rev().cloned());
+
+        Some(GameResults { places })
+    }
+
+    // Gets the random number generator for the next random event
+    // It is seeded from the game seed and the length of the move log, so the same log always draws the same numbers
+    // ChaCha is used over StdRng since its output is guaranteed not to change between versions of rand
+    pub fn get_event_rng(&self) -> ChaCha8Rng {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        rng.set_stream(self.moves.len() as u64 + 1);
+        rng
+    }
+
+    // Picks a random empty position for the next heart pickup, if there is any room for one
+    pub fn pick_heart_spawn(&self) -> Option<BoardPos> {
+        self.current_board.get_empty_positions().choose(&mut self.get_event_rng()).cloned()
     }
 }
\ No newline at end of file
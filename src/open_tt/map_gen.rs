@@ -0,0 +1,289 @@
+// Seeded generator for random map layouts
+// Cells are rolled as water, forest or empty by the requested densities, then any regions a tank couldn't reach
+// are joined back up by draining the water between them and the largest region
+use std::collections::{HashSet, VecDeque};
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use super::*;
+use board_object::{FOREST, WATER};
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MapGenOptions {
+    pub size_x : u16,
+    pub size_y : u16,
+    pub forest_density : f64, // Chance of each cell being forest, between 0 and 1
+    pub water_density : f64, // Chance of each cell being water, between 0 and 1
+    pub symmetric : bool // Whether the layout looks the same after a half turn around the centre of the board
+}
+
+impl Default for MapGenOptions {
+    fn default() -> Self {
+        Self { size_x: 20, size_y: 20, forest_density: 0.15, water_density: 0.1, symmetric: false }
+    }
+}
+
+#[derive(Debug)]
+pub enum MapGenError {
+    ZeroSize,
+    MapTooLarge,
+    InvalidDensity
+}
+
+
+impl Map {
+    // Generates a layout from the options, the same options and seed always give the same map
+    pub fn generate(options : &MapGenOptions, seed : u64) -> Result<Map, MapGenError> {
+        // Step 1: Check the options make sense
+        if options.size_x == 0 || options.size_y == 0 {
+            return Err(MapGenError::ZeroSize);
+        }
+        if usize::from(options.size_x) * usize::from(options.size_y) > MAX_MAP_CELLS {
+            return Err(MapGenError::MapTooLarge);
+        }
+        let densities_valid = (0.0..=1.0).contains(&options.forest_density)
+            && (0.0..=1.0).contains(&options.water_density)
+            && options.forest_density + options.water_density <= 1.0;
+        if !densities_valid {
+            return Err(MapGenError::InvalidDensity);
+        }
+
+        let mut map = Map {
+            size_x: options.size_x,
+            size_y: options.size_y,
+            spawn_strategy: if options.symmetric {SpawnStrategy::Symmetric} else {SpawnStrategy::Random},
+            ..Default::default()
+        };
+        let mut board = Board { size_x: map.size_x, size_y: map.size_y, ..Default::default() };
+
+        // Step 2: Roll each cell, with symmetry on only the first half of the board is rolled and the rest is mirrored from it
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        for y in 0..map.size_y {
+            for x in 0..map.size_x {
+                let pos = BoardPos(x, y);
+                let mirrored = map.get_mirrored_pos(&pos);
+                if options.symmetric && (mirrored.1, mirrored.0) < (y, x) {
+                    continue;
+                }
+
+                let roll: f64 = rng.gen();
+                let type_flags = if roll < options.water_density {
+                    WATER
+                } else if roll < options.water_density + options.forest_density {
+                    FOREST
+                } else {
+                    continue;
+                };
+
+                board.objects.insert(pos, BoardObject { type_flags });
+                if options.symmetric {
+                    board.objects.insert(mirrored, BoardObject { type_flags });
+                }
+            }
+        }
+
+        // Step 3: Join every region to the largest one, each pass joins them all at once
+        // Symmetric paths can join a mirrored region to something other than the largest region, so passes repeat until there is only one
+        loop {
+            let mut regions = get_traversable_regions(&board);
+            if regions.len() <= 1 {
+                break;
+            }
+
+            // Ties go to the region found first, regions are found in row order so this stays deterministic
+            let main_ind = regions.iter().enumerate().max_by_key(|(ind, r)| (r.len(), std::cmp::Reverse(*ind))).unwrap().0;
+            let main = regions.swap_remove(main_ind);
+            let nearest = get_nearest_in_region(&board, &main);
+
+            for region in regions.iter() {
+                let start = region[0].clone();
+                let end = nearest[usize::from(start.1) * usize::from(board.size_x) + usize::from(start.0)].clone();
+                for pos in get_straight_path(&start, &end) {
+                    drain_pos(&mut board, &pos);
+                    if options.symmetric {
+                        drain_pos(&mut board, &map.get_mirrored_pos(&pos));
+                    }
+                }
+            }
+        }
+
+        // Step 4: Write out the objects in row order so the item list doesn't depend on hashing
+        let mut positions: Vec<&BoardPos> = board.objects.keys().collect();
+        positions.sort_by_key(|pos| (pos.1, pos.0));
+        map.items = positions.into_iter()
+            .map(|pos| MapItem::BoardObjectItem(board.objects[pos].type_flags, pos.clone()))
+            .collect();
+
+        Ok(map)
+    }
+}
+
+
+// Gets the closest position of the region to every position on the board, indexed by row then column
+// Found with a search spreading out from the whole region at once, so the board is only gone over once
+fn get_nearest_in_region(board : &Board, region : &[BoardPos]) -> Vec<BoardPos> {
+    let width = usize::from(board.size_x);
+    let mut nearest: Vec<Option<BoardPos>> = vec![None; width * usize::from(board.size_y)];
+
+    let mut starts: Vec<BoardPos> = region.to_vec();
+    starts.sort_by_key(|pos| (pos.1, pos.0));
+    for pos in starts.iter() {
+        nearest[usize::from(pos.1) * width + usize::from(pos.0)] = Some(pos.clone());
+    }
+
+    let mut to_visit: VecDeque<BoardPos> = starts.into();
+    while let Some(pos) = to_visit.pop_front() {
+        let source = nearest[usize::from(pos.1) * width + usize::from(pos.0)].clone();
+        for dy in -1i32..=1 {
+            for dx in -1i32..=1 {
+                let (nx, ny) = (i32::from(pos.0) + dx, i32::from(pos.1) + dy);
+                if nx < 0 || ny < 0 || nx >= i32::from(board.size_x) || ny >= i32::from(board.size_y) {
+                    continue;
+                }
+
+                let ind = ny as usize * width + nx as usize;
+                if nearest[ind].is_none() {
+                    nearest[ind] = source.clone();
+                    to_visit.push_back(BoardPos(nx as u16, ny as u16));
+                }
+            }
+        }
+    }
+
+    // Every position is reached since the search can go through anything
+    nearest.into_iter().map(|pos| pos.unwrap()).collect()
+}
+
+// Removes the object at the position if it stops tanks moving through
+fn drain_pos(board : &mut Board, pos : &BoardPos) {
+    if !board.is_pos_traversable(pos) {
+        board.objects.remove(pos);
+    }
+}
+
+// Gets the positions on the way from start to end, stepping diagonally while both coordinates differ
+fn get_straight_path(start : &BoardPos, end : &BoardPos) -> Vec<BoardPos> {
+    let mut out: Vec<BoardPos> = Vec::new();
+    let mut current = start.clone();
+    while current != *end {
+        current = BoardPos(step_towards(current.0, end.0), step_towards(current.1, end.1));
+        out.push(current.clone());
+    }
+    out
+}
+
+fn step_towards(from : u16, to : u16) -> u16 {
+    match from.cmp(&to) {
+        std::cmp::Ordering::Less => from + 1,
+        std::cmp::Ordering::Greater => from - 1,
+        std::cmp::Ordering::Equal => from
+    }
+}
+
+// Splits the traversable positions of the board into regions a tank can move between
+// Tanks can move diagonally, so all eight neighbours of a position are connected to it
+pub fn get_traversable_regions(board : &Board) -> Vec<Vec<BoardPos>> {
+    let mut seen: HashSet<BoardPos> = HashSet::new();
+    let mut regions: Vec<Vec<BoardPos>> = Vec::new();
+
+    for y in 0..board.size_y {
+        for x in 0..board.size_x {
+            let start = BoardPos(x, y);
+            if seen.contains(&start) || !board.is_pos_traversable(&start) {
+                continue;
+            }
+
+            // Flood fill out from the first position of the region not seen yet
+            seen.insert(start.clone());
+            let mut region: Vec<BoardPos> = Vec::new();
+            let mut to_visit: Vec<BoardPos> = vec![start];
+            while let Some(pos) = to_visit.pop() {
+                for dx in -1i32..=1 {
+                    for dy in -1i32..=1 {
+                        let (nx, ny) = (i32::from(pos.0) + dx, i32::from(pos.1) + dy);
+                        if nx < 0 || ny < 0 || nx >= i32::from(board.size_x) || ny >= i32::from(board.size_y) {
+                            continue;
+                        }
+
+                        let next = BoardPos(nx as u16, ny as u16);
+                        if !seen.contains(&next) && board.is_pos_traversable(&next) {
+                            seen.insert(next.clone());
+                            to_visit.push(next);
+                        }
+                    }
+                }
+                region.push(pos);
+            }
+            regions.push(region);
+        }
+    }
+
+    regions
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_board(map : &Map) -> Board {
+        Board {
+            size_x: map.size_x,
+            size_y: map.size_y,
+            objects: map.items.iter()
+                .map(|i| match i {
+                    MapItem::BoardObjectItem(t, p) => (p.clone(), BoardObject{type_flags : *t})
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_map() {
+        let options = MapGenOptions { water_density: 0.4, ..Default::default() };
+        let first = serde_json::to_string(&Map::generate(&options, 42).unwrap()).unwrap();
+        let second = serde_json::to_string(&Map::generate(&options, 42).unwrap()).unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn generated_maps_are_connected() {
+        for seed in 0..50 {
+            for symmetric in [false, true] {
+                let options = MapGenOptions { size_x: 12 + (seed % 9) as u16, size_y: 10, forest_density: 0.2, water_density: 0.5, symmetric };
+                let map = Map::generate(&options, seed).unwrap();
+                assert_eq!(get_traversable_regions(&to_board(&map)).len(), 1, "seed {} symmetric {}", seed, symmetric);
+            }
+        }
+    }
+
+    #[test]
+    fn symmetric_maps_mirror_every_object() {
+        let options = MapGenOptions { size_x: 15, size_y: 9, forest_density: 0.3, water_density: 0.3, symmetric: true };
+        let map = Map::generate(&options, 7).unwrap();
+        let board = to_board(&map);
+        for (pos, object) in board.objects.iter() {
+            let mirrored = board.objects.get(&map.get_mirrored_pos(pos));
+            assert_eq!(mirrored.map(|o| o.type_flags), Some(object.type_flags), "{:?}", pos);
+        }
+        assert_eq!(map.spawn_strategy, SpawnStrategy::Symmetric);
+    }
+
+    #[test]
+    fn large_mostly_water_map_is_connected() {
+        let options = MapGenOptions { size_x: 256, size_y: 256, forest_density: 0.0, water_density: 0.7, symmetric: false };
+        let map = Map::generate(&options, 1).unwrap();
+        assert_eq!(get_traversable_regions(&to_board(&map)).len(), 1);
+    }
+
+    #[test]
+    fn bad_options_are_rejected() {
+        assert!(matches!(Map::generate(&MapGenOptions { size_x: 0, ..Default::default() }, 0), Err(MapGenError::ZeroSize)));
+        assert!(matches!(Map::generate(&MapGenOptions { size_x: 300, size_y: 300, ..Default::default() }, 0), Err(MapGenError::MapTooLarge)));
+        assert!(matches!(Map::generate(&MapGenOptions { forest_density: 0.6, water_density: 0.6, ..Default::default() }, 0), Err(MapGenError::InvalidDensity)));
+    }
+}
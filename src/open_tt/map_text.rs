@@ -0,0 +1,204 @@
+// Plain text format for maps, so layouts can be written by hand in a text editor
+// Each line is a row of the board starting from y = 0, each character in it is one cell starting from x = 0
+//
+//   .  empty
+//   F  forest
+//   W  water
+//   H  heart pickup
+//   S  empty spawn point
+//
+// If any S cells are present tanks can only start on them. Every row has to be the same length. Boards rendered with their tanks show each tank as its ID,
+// 0-9 then a-z, with * for any ID past that
+use std::fmt;
+
+use super::*;
+use board_object::{FOREST, HEART, WATER};
+
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MapParseError {
+    pub line : usize, // Line and column are counted from 1, as in a text editor
+    pub column : usize,
+    pub kind : MapParseErrorKind
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MapParseErrorKind {
+    EmptyMap,
+    MapTooLarge,
+    UnknownCell(char),
+    RowLengthMismatch{expected : usize, found : usize}
+}
+
+impl fmt::Display for MapParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            MapParseErrorKind::EmptyMap => write!(f, "line {}: map has no rows", self.line),
+            MapParseErrorKind::MapTooLarge => write!(f, "line {}, column {}: map is too large", self.line, self.column),
+            MapParseErrorKind::UnknownCell(c) => write!(f, "line {}, column {}: unknown cell '{}'", self.line, self.column, c),
+            MapParseErrorKind::RowLengthMismatch{expected, found} => write!(f, "line {}: row is {} cells long, expected {}", self.line, found, expected)
+        }
+    }
+}
+
+
+// Gets the character a board object is written as, objects with no character of their own are written as ?
+fn object_char(type_flags : u8) -> char {
+    match type_flags {
+        FOREST => 'F',
+        WATER => 'W',
+        HEART => 'H',
+        _ => '?'
+    }
+}
+
+// Gets the type flags of the object a character stands for, Ok(None) is an empty cell
+fn char_object(c : char) -> Result<Option<u8>, MapParseErrorKind> {
+    match c {
+        '.' => Ok(None),
+        'F' => Ok(Some(FOREST)),
+        'W' => Ok(Some(WATER)),
+        'H' => Ok(Some(HEART)),
+        _ => Err(MapParseErrorKind::UnknownCell(c))
+    }
+}
+
+// Gets the character a tank is drawn as
+fn tank_char(p_id : u8) -> char {
+    match p_id {
+        0..=9 => char::from(b'0' + p_id),
+        10..=35 => char::from(b'a' + p_id - 10),
+        _ => '*'
+    }
+}
+
+
+impl Map {
+    // Parses a map from its text form, trailing blank lines and trailing \r are ignored
+    pub fn from_text(text : &str) -> Result<Map, MapParseError> {
+        let rows: Vec<&str> = text.trim_end().lines().map(|l| l.trim_end_matches('\r')).collect();
+
+        let size_x = match rows.first() {
+            Some(r) if !r.is_empty() => r.chars().count(),
+            _ => {return Err(MapParseError { line: 1, column: 1, kind: MapParseErrorKind::EmptyMap });}
+        };
+
+        // Board sizes are stored as u16, so point at the first row or column past that
+        let max_size = usize::from(u16::MAX);
+        if rows.len() > max_size {
+            return Err(MapParseError { line: max_size + 1, column: 1, kind: MapParseErrorKind::MapTooLarge });
+        }
+        if size_x > max_size {
+            return Err(MapParseError { line: 1, column: max_size + 1, kind: MapParseErrorKind::MapTooLarge });
+        }
+
+        let mut items: Vec<MapItem> = Vec::new();
+        let mut spawns: Vec<BoardPos> = Vec::new();
+        for (y, row) in rows.iter().enumerate() {
+            let found = row.chars().count();
+            if found != size_x {
+                return Err(MapParseError { line: y + 1, column: 1, kind: MapParseErrorKind::RowLengthMismatch{expected: size_x, found} });
+            }
+
+            for (x, c) in row.chars().enumerate() {
+                if c == 'S' {
+                    spawns.push(BoardPos(x as u16, y as u16));
+                    continue;
+                }
+
+                match char_object(c) {
+                    Ok(Some(flags)) => items.push(MapItem::BoardObjectItem(flags, BoardPos(x as u16, y as u16))),
+                    Ok(None) => {},
+                    Err(kind) => {return Err(MapParseError { line: y + 1, column: x + 1, kind });}
+                }
+            }
+        }
+
+        Ok(Map { items, size_x: size_x as u16, size_y: rows.len() as u16, spawns, ..Default::default() })
+    }
+
+    // Writes the map in its text form, spawns with an object on them are written as the object
+    pub fn to_text(&self) -> String {
+        let mut cells = vec![vec!['.'; usize::from(self.size_x)]; usize::from(self.size_y)];
+        for pos in self.spawns.iter() {
+            if let Some(cell) = cells.get_mut(usize::from(pos.1)).and_then(|row| row.get_mut(usize::from(pos.0))) {
+                *cell = 'S';
+            }
+        }
+        for item in self.items.iter() {
+            match item {
+                MapItem::BoardObjectItem(flags, pos) => {
+                    if let Some(cell) = cells.get_mut(usize::from(pos.1)).and_then(|row| row.get_mut(usize::from(pos.0))) {
+                        *cell = object_char(*flags);
+                    }
+                }
+            }
+        }
+
+        cells.into_iter().map(|row| row.into_iter().collect::<String>() + "\n").collect()
+    }
+}
+
+
+impl Board {
+    // Draws the board in the map text form, with each living tank drawn over whatever is under it
+    pub fn to_text(&self) -> String {
+        let mut cells = vec![vec!['.'; usize::from(self.size_x)]; usize::from(self.size_y)];
+        for (pos, object) in self.objects.iter() {
+            if let Some(cell) = cells.get_mut(usize::from(pos.1)).and_then(|row| row.get_mut(usize::from(pos.0))) {
+                *cell = object_char(object.type_flags);
+            }
+        }
+
+        for (p_id, tank) in self.players.iter() {
+            if let Some(cell) = cells.get_mut(usize::from(tank.position.1)).and_then(|row| row.get_mut(usize::from(tank.position.0))) {
+                *cell = tank_char(*p_id);
+            }
+        }
+
+        cells.into_iter().map(|row| row.into_iter().collect::<String>() + "\n").collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_cell_kind() {
+        let map = Map::from_text("F.W\r\nHS.\n\n").unwrap();
+
+        assert_eq!((map.size_x, map.size_y), (3, 2));
+        assert_eq!(map.spawns, vec![BoardPos(1, 1)]);
+        let items: Vec<(u8, BoardPos)> = map.items.iter().map(|i| match i { MapItem::BoardObjectItem(f, p) => (*f, p.clone()) }).collect();
+        assert_eq!(items, vec![(FOREST, BoardPos(0, 0)), (WATER, BoardPos(2, 0)), (HEART, BoardPos(0, 1))]);
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let text = "F..W\nS.H.\n..SF\n";
+        assert_eq!(Map::from_text(text).unwrap().to_text(), text);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        assert_eq!(Map::from_text("").unwrap_err(), MapParseError { line: 1, column: 1, kind: MapParseErrorKind::EmptyMap });
+        assert_eq!(Map::from_text("..\n.X\n").unwrap_err(), MapParseError { line: 2, column: 2, kind: MapParseErrorKind::UnknownCell('X') });
+        assert_eq!(
+            Map::from_text("...\n..\n").unwrap_err(),
+            MapParseError { line: 2, column: 1, kind: MapParseErrorKind::RowLengthMismatch{expected: 3, found: 2} }
+        );
+        assert_eq!(Map::from_text("..\n.X\n").unwrap_err().to_string(), "line 2, column 2: unknown cell 'X'");
+    }
+
+    #[test]
+    fn boards_draw_tanks_over_objects() {
+        let mut board = Board { size_x: 3, size_y: 1, ..Default::default() };
+        board.objects.insert(BoardPos(0, 0), BoardObject { type_flags: FOREST });
+        board.objects.insert(BoardPos(2, 0), BoardObject { type_flags: WATER });
+        board.players.insert(11, PlayerTank::new(BoardPos(0, 0), &GameRules::default()));
+
+        assert_eq!(board.to_text(), "b.W\n");
+    }
+}
@@ -0,0 +1,90 @@
+// Checks a layout is playable before it is accepted for a game
+use std::collections::HashSet;
+
+use super::*;
+use board_object::KNOWN_FLAGS;
+use map_gen::get_traversable_regions;
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum MapIssue {
+    ZeroSize,
+    MapTooLarge{cells : usize, max : usize},
+    OutOfBounds(BoardPos), // An item is placed off the board
+    SpawnOutOfBounds(BoardPos),
+    DuplicatePosition(BoardPos), // More than one item is placed at the same position
+    UnknownFlags(BoardPos, u8), // The flag bits set on the item that don't mean anything
+    UnreachableRegion{start : BoardPos, size : usize}, // A region tanks can't get to from the rest of the board
+    TooFewSpawns{needed : u8, available : usize}
+}
+
+
+impl Map {
+    // Gets everything wrong with the layout for a game of up to max_players tanks, an empty list means it is fine to play on
+    pub fn validate(&self, max_players : u8) -> Vec<MapIssue> {
+        let mut issues: Vec<MapIssue> = Vec::new();
+
+        // Step 1: Check the board itself, the rest of the checks only make sense on a board of a usable size
+        if self.size_x == 0 || self.size_y == 0 {
+            issues.push(MapIssue::ZeroSize);
+            return issues;
+        }
+
+        let cells = usize::from(self.size_x) * usize::from(self.size_y);
+        if cells > MAX_MAP_CELLS {
+            issues.push(MapIssue::MapTooLarge{cells, max: MAX_MAP_CELLS});
+            return issues;
+        }
+
+        // Step 2: Check each item
+        let mut seen: HashSet<BoardPos> = HashSet::new();
+        for item in self.items.iter() {
+            match item {
+                MapItem::BoardObjectItem(flags, pos) => {
+                    if pos.0 >= self.size_x || pos.1 >= self.size_y {
+                        issues.push(MapIssue::OutOfBounds(pos.clone()));
+                    }
+                    if !seen.insert(pos.clone()) {
+                        issues.push(MapIssue::DuplicatePosition(pos.clone()));
+                    }
+                    if flags & !KNOWN_FLAGS != 0 {
+                        issues.push(MapIssue::UnknownFlags(pos.clone(), flags & !KNOWN_FLAGS));
+                    }
+                }
+            }
+        }
+
+        for pos in self.spawns.iter() {
+            if pos.0 >= self.size_x || pos.1 >= self.size_y {
+                issues.push(MapIssue::SpawnOutOfBounds(pos.clone()));
+            }
+        }
+
+        // Step 3: Every region but the largest is cut off from the rest of the board
+        let board = Board {
+            size_x: self.size_x,
+            size_y: self.size_y,
+            objects: self.items.iter()
+                .map(|i| match i {
+                    MapItem::BoardObjectItem(t, p) => (p.clone(), BoardObject{type_flags : *t})
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let regions = get_traversable_regions(&board);
+        let main_ind = regions.iter().enumerate().max_by_key(|(ind, r)| (r.len(), std::cmp::Reverse(*ind))).map(|(ind, _)| ind);
+        for (ind, region) in regions.iter().enumerate() {
+            if Some(ind) != main_ind {
+                issues.push(MapIssue::UnreachableRegion{start: region[0].clone(), size: region.len()});
+            }
+        }
+
+        // Step 4: Check there is somewhere for every tank to start
+        if !self.has_room_for(max_players) {
+            issues.push(MapIssue::TooFewSpawns{needed: max_players, available: self.get_legal_spawns().len()});
+        }
+
+        issues
+    }
+}
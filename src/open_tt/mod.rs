@@ -4,53 +4,192 @@
 pub mod board_object;
 pub mod board;
 pub mod game;
+pub mod replay;
+pub mod map_text;
+pub mod spawns;
+pub mod map_gen;
+pub mod map_validation;
+pub mod rules_validation;
 
 
 use std::{cmp::{max, min}, collections::HashMap};
-use board_object::{BoardObject, INPASSABLE};
-use serde::{Deserialize, Serialize};
+use board_object::{BoardObject, BLOCK_SIGHT, INPASSABLE};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 
-const PLAYER_MOVE_DIST :u16= 1;
-const PLAYER_SHOOT_DIST :u16= 3;
+// Number of moves between each board snapshot a game keeps
+pub const SNAPSHOT_INTERVAL :usize= 100;
+
+// Largest number of cells a map can have, so a single layout can't make the server do unbounded work
+pub const MAX_MAP_CELLS :usize= 256 * 256;
+
+
+// The rules a game is played by, set by the admin before the game starts so lobbies can play variants
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameRules {
+    pub move_distance : u16,
+    pub base_range : u16, // Starting range of each tank
+    pub sight_distance : u16,
+    pub starting_hitpoints : u8,
+    pub starting_action_points : u8,
+    pub range_upgrade_cost : u8,
+    pub heart_hp_cap : u8, // Hearts can't heal a tank past this many hitpoints
+    pub jury_votes_needed : u8, // Number of jury votes a tank needs in one AP period to get a bonus AP
+    pub friendly_fire : bool, // Whether a tank can damage itself by shooting its own position
+    pub ap_tick_seconds : u64,
+    pub heart_spawn_seconds : u64,
+    pub game_mode : GameMode,
+    pub turn_action_points : u8, // Action points a tank starts each of its turns with in turn based games
+    pub phase_seconds : u64 // How long players have to give their orders each phase in simultaneous games
+}
+
+// How players take their actions
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameMode {
+    #[default]
+    RealTime, // Anyone can act whenever they have AP, AP is handed out on a timer
+    TurnBased, // Tanks take turns in order of ID, each turn starts with a fixed AP budget
+    Simultaneous // Tanks give sealed orders which are all carried out together when the phase ends
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            move_distance: 1,
+            base_range: 3,
+            sight_distance: 5,
+            starting_hitpoints: 3,
+            starting_action_points: 0,
+            range_upgrade_cost: 3,
+            heart_hp_cap: 3,
+            jury_votes_needed: 3,
+            friendly_fire: true,
+            ap_tick_seconds: 60 * 60 * 24,
+            heart_spawn_seconds: 60 * 60 * 24 * 3,
+            game_mode: GameMode::RealTime,
+            turn_action_points: 3,
+            phase_seconds: 60 * 60 * 24
+        }
+    }
+}
 
 
 // Represents a single game of Tank Tactics
 pub struct  Game {
+    pub map: Map, // The layout the game was started on
     pub starting_board: Board,
     pub current_board: Board,
     pub moves: Vec<Action>,
-    pub game_state: GameState
+    pub events: Vec<Vec<GameEvent>>, // What happened because of each move, indexed the same as moves
+    pub game_state: GameState,
+    pub snapshots: Vec<Board>, // Copies of the board taken every SNAPSHOT_INTERVAL moves, so old turns can be rebuilt quickly
+    pub seed: u64, // Seed all of the game's randomness is drawn from, so a game can be rebuilt exactly from its log
+    pub pending_orders: HashMap<u8, Vec<Action>> // Orders given so far this phase in simultaneous games, keyed by tank ID
 }
 
 impl Default for Game {
     fn default() -> Self {
         Self { 
-            starting_board: Board { size_x: 0, size_y: 0, players: HashMap::new(), objects: HashMap::new() }, 
-            current_board: Board { size_x: 0, size_y: 0, players: HashMap::new(), objects: HashMap::new() }, 
+            map: Map::default(),
+            starting_board: Board::default(), 
+            current_board: Board::default(), 
             moves: Vec::new(), 
-            game_state: GameState::InProgress }
+            events: Vec::new(),
+            game_state: GameState::InProgress,
+            snapshots: Vec::new(),
+            seed: 0,
+            pending_orders: HashMap::new() }
     }
 }
 
 // Represents a game board, consisting of living players and board objects
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Board {
     pub size_x : u16,
     pub size_y : u16,
     pub players : HashMap<u8, PlayerTank>, // Players are referenced by their ID
-    pub objects : HashMap<BoardPos, BoardObject> // Board objects are refenced by their position, since they are static
+    #[serde(with = "pos_keyed_map")]
+    pub objects : HashMap<BoardPos, BoardObject>, // Board objects are refenced by their position, since they are static
+    #[serde(default)]
+    pub graveyard : Vec<u8>, // IDs of dead players in the order they died, these players make up the jury
+    #[serde(default)]
+    pub eliminations : Vec<Vec<u8>>, // The graveyard grouped by the action that killed them, tanks killed together share a group
+    #[serde(default)]
+    pub jury_votes : HashMap<u8, u8>, // Votes cast by the jury this AP period, maps voter ID to target ID
+    #[serde(default)]
+    pub memories : HashMap<u8, PlayerMemory>, // What each living player has seen of the board, referenced by player ID
+    #[serde(default)]
+    pub rules : GameRules,
+    #[serde(default)]
+    pub active_player : Option<u8>, // The tank whose turn it is in turn based games, always None in other games
+    #[serde(default)]
+    pub last_resolution : Option<ResolutionReport> // What happened in the last phase of a simultaneous game
+}
+
+
+// What a player knows about the parts of the board they can't currently see
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PlayerMemory {
+    pub last_seen_tanks : HashMap<u8, BoardPos>, // Where each other tank was when this player last saw it
+    #[serde(with = "pos_keyed_map")]
+    pub known_objects : HashMap<BoardPos, BoardObject> // Objects as they were when this player last saw their position
+}
+
+
+// A player's view of a board, only containing what their tank can see or remembers
+#[derive(Debug, Serialize)]
+pub struct BoardView {
+    pub size_x : u16,
+    pub size_y : u16,
+    pub visible_tanks : HashMap<u8, PlayerTank>,
+    pub last_seen_tanks : HashMap<u8, BoardPos>, // Last known positions of tanks that aren't visible
+    #[serde(with = "pos_keyed_map")]
+    pub known_objects : HashMap<BoardPos, BoardObject>,
+    pub graveyard : Vec<u8>,
+    pub active_player : Option<u8>,
+    pub last_resolution : Option<ResolutionReport>, // Only the outcomes of the player's own orders
+    pub phase_deadline : Option<i64> // Unix time in milliseconds the current phase of a simultaneous game ends, filled in by the server
+}
+
+
+// Serde helper for maps keyed by board position, JSON only allows string keys so these are stored as a list of pairs
+mod pos_keyed_map {
+    use super::*;
+
+    pub fn serialize<S: Serializer, V: Serialize>(map: &HashMap<BoardPos, V>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(deserializer: D) -> Result<HashMap<BoardPos, V>, D::Error> {
+        let pairs: Vec<(BoardPos, V)> = Vec::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
 }
 
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Map {
     pub items : Vec<MapItem>,
     pub size_x : u16,
-    pub size_y : u16
+    pub size_y : u16,
+    #[serde(default)]
+    pub spawns : Vec<BoardPos>, // Positions tanks may start at, if empty any position a tank can stand on is used
+    #[serde(default)]
+    pub spawn_strategy : SpawnStrategy
+}
+
+// How the starting position of each tank is picked from the map's spawns
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpawnStrategy {
+    #[default]
+    Random,
+    FixedOrder, // Tank n starts at the nth spawn
+    SpreadOut, // Spawns are picked to be as far from each other as possible
+    Symmetric // Spawns are picked in pairs mirrored around the centre of the board
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MapItem {
     BoardObjectItem(u8, BoardPos)
 }
@@ -82,11 +221,25 @@ pub struct PlayerTank {
     pub position : BoardPos,
     pub hitpoints : u8,
     pub action_points : u8,
+    #[serde(default = "PlayerTank::default_range")]
+    pub range : u16, // How far the tank can shoot or give to
+}
+
+impl PlayerTank {
+    // Creates a tank at the given position, starting with the stats given by the rules
+    pub fn new(position : BoardPos, rules : &GameRules) -> PlayerTank {
+        Self { position, hitpoints: rules.starting_hitpoints, action_points: rules.starting_action_points, range: rules.base_range }
+    }
+
+    // Boards stored before tanks had their own range fall back to the starting range
+    fn default_range() -> u16 {
+        GameRules::default().base_range
+    }
 }
 
 impl Default for PlayerTank {
     fn default() -> Self {
-        Self { position: BoardPos(0, 0), hitpoints: 3, action_points: 0}
+        Self::new(BoardPos(0, 0), &GameRules::default())
     }
 }
 
@@ -111,25 +264,108 @@ pub enum BoardObjectHitResult {
     NoEffect
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ActionError {
     OutOfBounds,
     SpaceOccupied,
     NoTargetFound,
     InvalidPlayerID,
     NotEnoughAP,
-    TargetTooFar
+    TargetTooFar,
+    NoLineOfSight,
+    FriendlyFire,
+    NotInJury,
+    AlreadyVoted,
+    NotYourTurn,
+    TooManyMoves, // A tank can only give one move order each phase
+    WrongGameMode // The action doesn't exist in the game's mode, like ending a turn in a real time game
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Action {
     TankMove(u8, BoardPos),
     TankShoot(u8, BoardPos),
     TankGiveAP(u8, BoardPos),
+    TankGiveHP(u8, BoardPos),
+    TankUpgradeRange(u8),
+    JuryVote(u8, u8), // A dead player votes for a living one to receive bonus AP, parameters are voter ID then target ID
+    APTick, // System event, every living tank gains an action point
+    SpawnHeart(BoardPos), // System event, a heart pickup appears at the given position
+    EndTurn(u8), // Turn based games only, the tank gives the turn to the next tank
+    ResolvePhase(Vec<Action>) // System event in simultaneous games, every order given this phase is carried out together
+}
+
+impl Action {
+    // Gets the id of the tank performing the action, system events have no acting tank
+    pub fn get_acting_player(&self) -> Option<u8> {
+        match self {
+            Action::TankMove(p_id, _) => Some(*p_id),
+            Action::TankShoot(p_id, _) => Some(*p_id),
+            Action::TankGiveAP(p_id, _) => Some(*p_id),
+            Action::TankGiveHP(p_id, _) => Some(*p_id),
+            Action::TankUpgradeRange(p_id) => Some(*p_id),
+            Action::JuryVote(voter, _) => Some(*voter),
+            Action::APTick => None,
+            Action::SpawnHeart(_) => None,
+            Action::EndTurn(p_id) => Some(*p_id),
+            Action::ResolvePhase(_) => None
+        }
+    }
 }
 
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum GameState {
     Pregame,
     InProgress, 
-    GameWon(u8) // State representing a won game, the parameter is the id of the winning player
+    GameWon(u8), // State representing a won game, the parameter is the id of the winning player
+    Draw // State representing a game where the last tanks were all killed at once
+}
+
+
+// Something that happened on the board because of an action, so clients can follow a game without comparing boards
+// Events are stored whole, the optional fields are only left out when an event is filtered for a player who couldn't see that part of it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameEvent {
+    Moved{tank : u8, from : Option<BoardPos>, to : Option<BoardPos>},
+    Shot{tank : Option<u8>, target : BoardPos},
+    Hit{tank : u8, hitpoints : u8}, // A tank lost a hitpoint, hitpoints is how many it has left
+    Killed{tank : u8},
+    ObjectDestroyed{pos : BoardPos},
+    APGiven{from : Option<u8>, to : Option<u8>, amount : u8}, // AP handed out by the game itself, like AP ticks and jury bonuses, is from no tank
+    HPGiven{from : Option<u8>, to : Option<u8>},
+    RangeUpgraded{tank : u8, range : u16},
+    PickupCollected{tank : u8, pos : BoardPos},
+    HeartSpawned{pos : BoardPos},
+    JuryVoted{voter : u8, target : u8},
+    TurnStarted{tank : u8, action_points : u8},
+    Won{tank : u8},
+    Draw
+}
+
+
+// What happened to each order when a phase of a simultaneous game was resolved
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResolutionReport {
+    pub outcomes: Vec<OrderOutcome>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OrderOutcome {
+    pub order: Action,
+    pub result: OrderResult
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum OrderResult {
+    Applied,
+    Bounced, // A move that clashed with another tank's move, the tank stays put and keeps its AP
+    Rejected(ActionError)
+}
+
+
+// Final standings of a finished game, each place holds the IDs of the tanks that tied for it
+#[derive(Debug, Serialize)]
+pub struct GameResults {
+    pub places: Vec<Vec<u8>>
 }
\ No newline at end of file
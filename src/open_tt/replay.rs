@@ -0,0 +1,190 @@
+// Self contained replay files for sharing games
+// A replay holds everything needed to rebuild a game from scratch, the layout, rules and seed recreate the starting board
+// and the move log is replayed on top of it
+use super::*;
+use game::{GameCreationError, MoveError};
+
+
+// Version of the replay format written by this build, bump whenever Replay changes shape
+pub const REPLAY_FORMAT_VERSION :u16= 4;
+
+// Marks the start of a binary replay, followed by the format version as two little endian bytes
+const BINARY_MAGIC :&[u8; 4]= b"OTTR";
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub version : u16,
+    pub map : Map,
+    pub rules : GameRules,
+    pub seed : u64,
+    pub player_names : Vec<String>, // Indexed by tank ID
+    pub moves : Vec<Action>
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    UnsupportedVersion(u16),
+    MalformedData(String),
+    TooManyPlayers,
+    GameCreationError(GameCreationError),
+    IllegalMove(usize, MoveError) // The index of the first move that couldn't be replayed and why
+}
+
+
+impl Replay {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(data : &str) -> Result<Replay, ReplayError> {
+        // Read the version on its own first so old or new replays get a useful error instead of a parse failure
+        #[derive(Deserialize)]
+        struct VersionOnly { version : u16 }
+
+        let version = serde_json::from_str::<VersionOnly>(data)
+            .map_err(|e| ReplayError::MalformedData(e.to_string()))?
+            .version;
+        if version != REPLAY_FORMAT_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+
+        serde_json::from_str(data).map_err(|e| ReplayError::MalformedData(e.to_string()))
+    }
+
+    // Compact binary form, the magic bytes and version followed by the bincode encoded replay
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = BINARY_MAGIC.to_vec();
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend(bincode::serialize(self).unwrap());
+        out
+    }
+
+    pub fn from_bytes(data : &[u8]) -> Result<Replay, ReplayError> {
+        if data.len() < 6 || &data[0..4] != BINARY_MAGIC {
+            return Err(ReplayError::MalformedData("Not an Open Tank Tactics replay".to_string()));
+        }
+
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version != REPLAY_FORMAT_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+
+        bincode::deserialize(&data[6..]).map_err(|e| ReplayError::MalformedData(e.to_string()))
+    }
+}
+
+
+impl Game {
+    // Packs the game up as a replay, player names are given in order of tank ID
+    pub fn export_replay(&self, player_names : Vec<String>) -> Replay {
+        Replay {
+            version: REPLAY_FORMAT_VERSION,
+            map: self.map.clone(),
+            rules: self.starting_board.rules.clone(),
+            seed: self.seed,
+            player_names,
+            moves: self.moves.clone()
+        }
+    }
+
+    // Rebuilds a game from a replay, checking every move is legal along the way
+    pub fn import_replay(replay : &Replay) -> Result<Game, ReplayError> {
+        if replay.version != REPLAY_FORMAT_VERSION {
+            return Err(ReplayError::UnsupportedVersion(replay.version));
+        }
+
+        let player_count = match u8::try_from(replay.player_names.len()) {
+            Ok(c) => c,
+            Err(_) => {return Err(ReplayError::TooManyPlayers);}
+        };
+
+        let mut game = Game::new(&player_count, &replay.map, &replay.rules, replay.seed)
+            .map_err(ReplayError::GameCreationError)?;
+
+        for (ind, action) in replay.moves.iter().enumerate() {
+            if let Err(e) = game.do_action(action.clone()) {
+                return Err(ReplayError::IllegalMove(ind, e));
+            }
+        }
+
+        Ok(game)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A short game on a two cell board, which tank starts on which cell depends on the seed
+    fn played_game() -> Game {
+        let map = Map { size_x: 2, size_y: 1, ..Default::default() };
+        let rules = GameRules { starting_action_points: 5, starting_hitpoints: 3, ..Default::default() };
+
+        let mut game = Game::new(&2, &map, &rules, 7).unwrap();
+        let (pos_0, pos_1) = (game.current_board.players[&0].position.clone(), game.current_board.players[&1].position.clone());
+        for action in [Action::TankShoot(0, pos_1), Action::APTick, Action::TankShoot(1, pos_0.clone()), Action::TankShoot(1, pos_0)] {
+            game.do_action(action).unwrap();
+        }
+        game
+    }
+
+    fn assert_same_game(replayed : &Game, original : &Game) {
+        assert_eq!(replayed.moves.len(), original.moves.len());
+        assert_eq!(replayed.events, original.events);
+        assert_eq!(replayed.current_board.players, original.current_board.players);
+        assert_eq!(replayed.current_board.graveyard, original.current_board.graveyard);
+    }
+
+    #[test]
+    fn json_replay_round_trips() {
+        let game = played_game();
+        let replay = Replay::from_json(&game.export_replay(vec!["a".to_string(), "b".to_string()]).to_json()).unwrap();
+
+        assert_eq!(replay.player_names, vec!["a", "b"]);
+        assert_same_game(&Game::import_replay(&replay).unwrap(), &game);
+    }
+
+    #[test]
+    fn binary_replay_round_trips() {
+        let game = played_game();
+        let replay = Replay::from_bytes(&game.export_replay(vec!["a".to_string(), "b".to_string()]).to_bytes()).unwrap();
+
+        assert_same_game(&Game::import_replay(&replay).unwrap(), &game);
+    }
+
+    #[test]
+    fn other_versions_and_bad_data_are_rejected() {
+        let mut replay = played_game().export_replay(vec!["a".to_string(), "b".to_string()]);
+        replay.version = REPLAY_FORMAT_VERSION + 1;
+
+        assert!(matches!(Replay::from_json(&replay.to_json()), Err(ReplayError::UnsupportedVersion(v)) if v == REPLAY_FORMAT_VERSION + 1));
+        assert!(matches!(Replay::from_bytes(&replay.to_bytes()), Err(ReplayError::UnsupportedVersion(_))));
+        assert!(matches!(Replay::from_bytes(b"nope"), Err(ReplayError::MalformedData(_))));
+        assert!(matches!(Replay::from_json("{}"), Err(ReplayError::MalformedData(_))));
+    }
+
+    #[test]
+    fn illegal_moves_are_caught() {
+        let mut replay = played_game().export_replay(vec!["a".to_string(), "b".to_string()]);
+        replay.moves.push(Action::TankMove(0, BoardPos(5, 0)));
+
+        assert!(matches!(Game::import_replay(&replay), Err(ReplayError::IllegalMove(4, _))));
+    }
+
+    #[test]
+    fn phases_with_two_moves_for_a_tank_are_caught() {
+        let mut map = Map::from_text("S..S\n").unwrap();
+        map.spawn_strategy = SpawnStrategy::FixedOrder;
+        let rules = GameRules { game_mode: GameMode::Simultaneous, starting_action_points: 1, move_distance: 2, ..Default::default() };
+
+        let mut replay = Game::new(&2, &map, &rules, 7).unwrap().export_replay(vec!["a".to_string(), "b".to_string()]);
+        replay.moves = vec![
+            Action::SpawnHeart(BoardPos(1, 0)),
+            Action::ResolvePhase(vec![Action::TankMove(0, BoardPos(1, 0)), Action::TankMove(0, BoardPos(2, 0))])
+        ];
+
+        assert!(matches!(Game::import_replay(&replay), Err(ReplayError::IllegalMove(1, MoveError::ActionError(ActionError::TooManyMoves)))));
+    }
+}
@@ -0,0 +1,64 @@
+// Checks a set of rules can be played by before they are accepted for a game
+use super::*;
+
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum RuleIssue {
+    NoStartingHitpoints, // Tanks would start dead
+    HeartCapBelowStart{heart_hp_cap : u8, starting_hitpoints : u8},
+    FreeRangeUpgrades, // Range could be upgraded without limit
+    ZeroPeriod(&'static str) // The named timer would have to fire continuously
+}
+
+
+impl GameRules {
+    // Gets everything wrong with the rules, an empty list means they are fine to play by
+    pub fn validate(&self) -> Vec<RuleIssue> {
+        let mut issues: Vec<RuleIssue> = Vec::new();
+
+        // Step 1: Check the tanks start out alive and can't be healed past what they start with
+        if self.starting_hitpoints == 0 {
+            issues.push(RuleIssue::NoStartingHitpoints);
+        }
+        if self.heart_hp_cap < self.starting_hitpoints {
+            issues.push(RuleIssue::HeartCapBelowStart{heart_hp_cap: self.heart_hp_cap, starting_hitpoints: self.starting_hitpoints});
+        }
+        if self.range_upgrade_cost == 0 {
+            issues.push(RuleIssue::FreeRangeUpgrades);
+        }
+
+        // Step 2: Check every timer has a period
+        let periods = [
+            ("ap_tick_seconds", self.ap_tick_seconds),
+            ("heart_spawn_seconds", self.heart_spawn_seconds),
+            ("phase_seconds", self.phase_seconds)
+        ];
+        for (name, seconds) in periods {
+            if seconds == 0 {
+                issues.push(RuleIssue::ZeroPeriod(name));
+            }
+        }
+
+        issues
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_rules_are_valid() {
+        assert!(GameRules::default().validate().is_empty());
+    }
+
+    #[test]
+    fn unplayable_rules_are_reported() {
+        let rules = GameRules { starting_hitpoints: 0, range_upgrade_cost: 0, heart_spawn_seconds: 0, ..Default::default() };
+        assert_eq!(rules.validate(), vec![RuleIssue::NoStartingHitpoints, RuleIssue::FreeRangeUpgrades, RuleIssue::ZeroPeriod("heart_spawn_seconds")]);
+
+        let rules = GameRules { starting_hitpoints: 4, heart_hp_cap: 3, ..Default::default() };
+        assert_eq!(rules.validate(), vec![RuleIssue::HeartCapBelowStart{heart_hp_cap: 3, starting_hitpoints: 4}]);
+    }
+}
@@ -0,0 +1,164 @@
+// Choosing where each tank starts on a map
+use std::collections::HashSet;
+
+use rand::{seq::SliceRandom, Rng};
+
+use super::*;
+use game::GameCreationError;
+
+
+impl Map {
+    // Gets the type flags of the object at each position, if the map has more than one item at a position the last one wins
+    fn get_object_flags(&self) -> HashMap<BoardPos, u8> {
+        self.items.iter()
+            .map(|item| match item {
+                MapItem::BoardObjectItem(flags, pos) => (pos.clone(), *flags)
+            })
+            .collect()
+    }
+
+    // Checks if a tank could start at the position, it has to be on the board and not inside anything inpassable
+    fn is_legal_spawn(&self, object_flags : &HashMap<BoardPos, u8>, pos : &BoardPos) -> bool {
+        pos.0 < self.size_x && pos.1 < self.size_y && match object_flags.get(pos) {
+            None => true,
+            Some(flags) => flags & INPASSABLE == 0
+        }
+    }
+
+    // Gets every position a tank could start at, in order
+    // These are the declared spawns if the map has any, otherwise every legal position on the board
+    pub fn get_legal_spawns(&self) -> Vec<BoardPos> {
+        let object_flags = self.get_object_flags();
+        if !self.spawns.is_empty() {
+            let mut seen: HashSet<BoardPos> = HashSet::new();
+            return self.spawns.iter()
+                .filter(|pos| self.is_legal_spawn(&object_flags, pos) && seen.insert((*pos).clone()))
+                .cloned()
+                .collect();
+        }
+
+        let mut out: Vec<BoardPos> = Vec::new();
+        for y in 0..self.size_y {
+            for x in 0..self.size_x {
+                let pos = BoardPos(x, y);
+                if self.is_legal_spawn(&object_flags, &pos) {
+                    out.push(pos);
+                }
+            }
+        }
+        out
+    }
+
+    // Picks a starting position for each tank using the map's spawn strategy, the position for tank n is at index n
+    pub fn pick_spawns<R: Rng>(&self, player_count : u8, rng : &mut R) -> Result<Vec<BoardPos>, GameCreationError> {
+        let count = usize::from(player_count);
+        let mut candidates = self.get_legal_spawns();
+        if candidates.len() < count {
+            return Err(GameCreationError::NotEnoughSpawnpoints);
+        }
+
+        match self.spawn_strategy {
+            SpawnStrategy::Random => {
+                candidates.shuffle(rng);
+                candidates.truncate(count);
+                Ok(candidates)
+            },
+            SpawnStrategy::FixedOrder => {
+                candidates.truncate(count);
+                Ok(candidates)
+            },
+            SpawnStrategy::SpreadOut => Ok(pick_spread_out(candidates, count, rng)),
+            SpawnStrategy::Symmetric => self.pick_symmetric(candidates, count, rng)
+        }
+    }
+
+    // Checks the map has room to start a game with the given number of players
+    // This counts the spawns instead of picking them, since only the symmetric strategy can run out of room with enough legal spawns
+    pub fn has_room_for(&self, player_count : u8) -> bool {
+        let count = usize::from(player_count);
+        let candidates = self.get_legal_spawns();
+
+        match self.spawn_strategy {
+            SpawnStrategy::Symmetric => {
+                let legal: HashSet<&BoardPos> = candidates.iter().collect();
+                let paired = candidates.iter()
+                    .filter(|pos| {
+                        let mirrored = self.get_mirrored_pos(pos);
+                        mirrored != **pos && legal.contains(&mirrored)
+                    })
+                    .count();
+                let has_centre = candidates.iter().any(|pos| self.get_mirrored_pos(pos) == *pos);
+                paired >= count - count % 2 && (count % 2 == 0 || has_centre)
+            },
+            _ => candidates.len() >= count
+        }
+    }
+
+    // Gets the position a half turn around the centre of the board
+    pub fn get_mirrored_pos(&self, pos : &BoardPos) -> BoardPos {
+        BoardPos(self.size_x - 1 - pos.0, self.size_y - 1 - pos.1)
+    }
+
+    // Picks spawns in pairs mirrored around the centre of the board so no tank starts with an advantage
+    // With an odd number of tanks the last one has to start on the centre cell
+    fn pick_symmetric<R: Rng>(&self, mut candidates : Vec<BoardPos>, count : usize, rng : &mut R) -> Result<Vec<BoardPos>, GameCreationError> {
+        let legal: HashSet<BoardPos> = candidates.iter().cloned().collect();
+        candidates.shuffle(rng);
+
+        let mut out: Vec<BoardPos> = Vec::new();
+        let mut used: HashSet<BoardPos> = HashSet::new();
+        for pos in candidates.iter() {
+            if out.len() + 2 > count {
+                break;
+            }
+
+            let mirrored = self.get_mirrored_pos(pos);
+            if mirrored == *pos || !legal.contains(&mirrored) || used.contains(pos) || used.contains(&mirrored) {
+                continue;
+            }
+
+            used.insert(pos.clone());
+            used.insert(mirrored.clone());
+            out.push(pos.clone());
+            out.push(mirrored);
+        }
+
+        if out.len() + 1 == count {
+            match candidates.iter().find(|pos| self.get_mirrored_pos(pos) == **pos) {
+                Some(centre) => out.push(centre.clone()),
+                None => {return Err(GameCreationError::NotEnoughSpawnpoints);}
+            }
+        }
+
+        if out.len() != count {
+            return Err(GameCreationError::NotEnoughSpawnpoints);
+        }
+        Ok(out)
+    }
+}
+
+
+// Picks spawns as far apart as possible, starting from a random one and then repeatedly taking
+// the candidate furthest from all the spawns picked so far. Ties are broken randomly
+fn pick_spread_out<R: Rng>(mut candidates : Vec<BoardPos>, count : usize, rng : &mut R) -> Vec<BoardPos> {
+    candidates.shuffle(rng);
+
+    // Distance from each candidate to the nearest spawn picked so far, kept in step with the candidates
+    let mut nearest: Vec<u16> = vec![u16::MAX; candidates.len()];
+    let mut out: Vec<BoardPos> = Vec::new();
+    while out.len() < count {
+        let best = nearest.iter()
+            .enumerate()
+            .max_by_key(|(ind, dist)| (**dist, std::cmp::Reverse(*ind))) // Earlier candidates win ties, the shuffle makes this random
+            .map(|(ind, _)| ind)
+            .unwrap();
+        let picked = candidates.swap_remove(best);
+        nearest.swap_remove(best);
+
+        for (pos, dist) in candidates.iter().zip(nearest.iter_mut()) {
+            *dist = min(*dist, pos.get_grid_dist(&picked));
+        }
+        out.push(picked);
+    }
+    out
+}